@@ -0,0 +1,534 @@
+//! Integer arithmetic, for `$(( ... ))` expansions and `(( ... ))` commands.
+//!
+//! Expressions use 64-bit signed integers and the operators of C, from lowest
+//! to highest precedence:
+//!
+//! | Operators                                          | Associativity |
+//! |----------------------------------------------------|---------------|
+//! | `,`                                                | left          |
+//! | `=` `+=` `-=` `*=` `/=` `%=` `<<=` `>>=` `&=` `^=` `\|=` | right   |
+//! | `? :`                                              | right         |
+//! | `\|\|`                                             | left          |
+//! | `&&`                                               | left          |
+//! | `\|`                                               | left          |
+//! | `^`                                                | left          |
+//! | `&`                                                | left          |
+//! | `==` `!=`                                          | left          |
+//! | `<` `<=` `>` `>=`                                  | left          |
+//! | `<<` `>>`                                          | left          |
+//! | `+` `-`                                            | left          |
+//! | `*` `/` `%`                                        | left          |
+//! | `**`                                               | right         |
+//! | unary `+` `-` `!` `~`, prefix `++` `--`            |               |
+//! | postfix `++` `--`                                  |               |
+//!
+//! Numbers may be decimal, octal with a leading `0`, hexadecimal with a leading
+//! `0x`, or in any base from 2 to 64 as `base#digits`. Names are shell
+//! variables: an unset or empty variable is zero, and a variable holding an
+//! expression is evaluated. `&&`, `||`, and `? :` only evaluate the operands
+//! they need.
+
+use std::{iter::Peekable, str::CharIndices};
+
+use failure::Fail;
+
+/// Access to shell variables for arithmetic.
+pub trait Variables {
+    fn get(&self, name: &str) -> Option<String>;
+    fn set(&mut self, name: &str, value: String) -> Result<(), ArithError>;
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Fail)]
+pub enum ArithError {
+    #[fail(display = "syntax error: {}", _0)]
+    Syntax(String),
+    #[fail(display = "{}: invalid number", _0)]
+    Number(String),
+    #[fail(display = "division by zero")]
+    DivideByZero,
+    #[fail(display = "exponent less than zero")]
+    NegativeExponent,
+    #[fail(display = "{}: expression recursion level exceeded", _0)]
+    Recursion(String),
+    #[fail(display = "{}: readonly variable", _0)]
+    ReadOnly(String),
+}
+
+/// How deeply variables holding expressions may refer to one another.
+const MAX_DEPTH: usize = 64;
+
+/// Evaluates an expression.
+pub fn eval(text: &str, vars: &mut impl Variables) -> Result<i64, ArithError> {
+    eval_at(text, vars, 0)
+}
+
+fn eval_at(text: &str, vars: &mut impl Variables, depth: usize) -> Result<i64, ArithError> {
+    let tokens = lex(text)?;
+    //  An empty expression is zero, as in `$(())`.
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.comma()?;
+    if let Some(tok) = parser.tokens.get(parser.pos) {
+        return Err(ArithError::Syntax(format!("unexpected `{}`", tok)));
+    }
+    Eval { vars, depth }.eval(&expr)
+}
+
+// ===== Lexing =====
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Num(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Name(n) => write!(f, "{}", n),
+            Token::Op(o) => write!(f, "{}", o),
+        }
+    }
+}
+
+/// Every operator, longest first so that the lexer matches greedily.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&",
+    "||", "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/",
+    "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=", "(", ")", ",",
+];
+
+fn lex(text: &str) -> Result<Vec<Token>, ArithError> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<CharIndices> = text.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            //  Numbers and names both run to the next non-word character; a
+            //  `#` and `@` may appear in numbers in a given base.
+            let mut end = i;
+            while let Some(&(j, c)) = chars.peek() {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '#' || c == '@' {
+                    end = j + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let word = &text[i .. end];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Num(number(word)?)
+            } else {
+                Token::Name(word.to_string())
+            });
+        } else {
+            let rest = &text[i ..];
+            let op = OPERATORS.iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| ArithError::Syntax(format!("unexpected `{}`", c)))?;
+            for _ in 0 .. op.len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses an integer constant.
+fn number(text: &str) -> Result<i64, ArithError> {
+    let bad = || ArithError::Number(text.to_string());
+    let (base, digits) = if let Some(n) = text.find('#') {
+        let base = text[.. n].parse::<u32>().map_err(|_| bad())?;
+        if base < 2 || base > 64 {
+            return Err(bad());
+        }
+        (base, &text[n + 1 ..])
+    } else if text.starts_with("0x") || text.starts_with("0X") {
+        (16, &text[2 ..])
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1 ..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return Err(bad());
+    }
+    digits.chars().try_fold(0i64, |acc, c| {
+        //  Digits beyond 9 are the letters, then `@` and `_` for base 64.
+        //  Up to base 36, letters of either case are the same digit.
+        let d = match c {
+            '0' ..= '9' => c as u32 - '0' as u32,
+            'a' ..= 'z' => c as u32 - 'a' as u32 + 10,
+            'A' ..= 'Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A' ..= 'Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(bad()),
+        };
+        if d >= base {
+            return Err(bad());
+        }
+        Ok(acc.wrapping_mul(base as i64).wrapping_add(d as i64))
+    })
+}
+
+// ===== Parsing =====
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// Assignment, with the operator it applies first (`=` for none).
+    Assign(&'static str, String, Box<Expr>),
+    /// `++` or `--`, before or after the name.
+    Step { name: String, by: i64, prefix: bool },
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+/// The binary operators, by precedence level from loosest to tightest.
+const LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ArithError> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(ArithError::Syntax(format!("expected `{}`", op)))
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, ArithError> {
+        let mut expr = self.assign()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            let rhs = self.assign()?;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn assign(&mut self) -> Result<Expr, ArithError> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if op.ends_with('=') && !["==", "!=", "<=", ">="].contains(op) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let value = self.assign()?;
+                //  `x += y` applies `+`; plain `=` applies nothing.
+                let apply = match op {
+                    "=" => "=",
+                    op => &OPERATORS.iter()
+                        .find(|o| **o == &op[.. op.len() - 1])
+                        .expect("every compound assignment has an operator")[..],
+                };
+                return Ok(Expr::Assign(apply, name, Box::new(value)));
+            }
+        }
+        self.cond()
+    }
+
+    fn cond(&mut self) -> Result<Expr, ArithError> {
+        let test = self.binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(test);
+        }
+        self.pos += 1;
+        let then = self.assign()?;
+        self.expect(":")?;
+        let other = self.assign()?;
+        Ok(Expr::Cond(Box::new(test), Box::new(then), Box::new(other)))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, ArithError> {
+        if level == LEVELS.len() {
+            return self.power();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn power(&mut self) -> Result<Expr, ArithError> {
+        let base = self.unary()?;
+        if self.peek_op() == Some("**") {
+            self.pos += 1;
+            let exp = self.power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exp)));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ArithError> {
+        match self.peek_op() {
+            Some(op @ "++") | Some(op @ "--") => {
+                self.pos += 1;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Name(name)) => {
+                        let name = name.clone();
+                        self.pos += 1;
+                        let by = if op == "++" { 1 } else { -1 };
+                        Ok(Expr::Step { name, by, prefix: true })
+                    },
+                    _ => Err(ArithError::Syntax(format!("`{}` needs a variable", op))),
+                }
+            },
+            Some(op @ "+") | Some(op @ "-") | Some(op @ "!") | Some(op @ "~") => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            },
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ArithError> {
+        let expr = self.primary()?;
+        if let Expr::Var(ref name) = expr {
+            if let Some(op @ "++") | Some(op @ "--") = self.peek_op() {
+                self.pos += 1;
+                let by = if op == "++" { 1 } else { -1 };
+                return Ok(Expr::Step { name: name.clone(), by, prefix: false });
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ArithError> {
+        let tok = self.tokens.get(self.pos).cloned()
+            .ok_or_else(|| ArithError::Syntax("operand expected".to_string()))?;
+        self.pos += 1;
+        match tok {
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::Name(name) => Ok(Expr::Var(name)),
+            Token::Op("(") => {
+                let inner = self.comma()?;
+                self.expect(")")?;
+                Ok(inner)
+            },
+            Token::Op(op) => Err(ArithError::Syntax(format!("unexpected `{}`", op))),
+        }
+    }
+}
+
+// ===== Evaluation =====
+
+struct Eval<'v, V: Variables> {
+    vars: &'v mut V,
+    depth: usize,
+}
+
+impl<'v, V: Variables> Eval<'v, V> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64, ArithError> {
+        Ok(match expr {
+            Expr::Num(n) => *n,
+            Expr::Var(name) => self.var(name)?,
+            Expr::Unary(op, e) => {
+                let v = self.eval(e)?;
+                match *op {
+                    "-" => v.wrapping_neg(),
+                    "!" => (v == 0) as i64,
+                    "~" => !v,
+                    _ => v,
+                }
+            },
+            Expr::Binary("&&", a, b) => (self.eval(a)? != 0 && self.eval(b)? != 0) as i64,
+            Expr::Binary("||", a, b) => (self.eval(a)? != 0 || self.eval(b)? != 0) as i64,
+            Expr::Binary(",", a, b) => {
+                self.eval(a)?;
+                self.eval(b)?
+            },
+            Expr::Binary(op, a, b) => {
+                let a = self.eval(a)?;
+                let b = self.eval(b)?;
+                apply(op, a, b)?
+            },
+            Expr::Assign(op, name, e) => {
+                let value = self.eval(e)?;
+                let value = match *op {
+                    "=" => value,
+                    op => apply(op, self.var(name)?, value)?,
+                };
+                self.vars.set(name, value.to_string())?;
+                value
+            },
+            Expr::Step { name, by, prefix } => {
+                let old = self.var(name)?;
+                let new = old.wrapping_add(*by);
+                self.vars.set(name, new.to_string())?;
+                if *prefix { new } else { old }
+            },
+            Expr::Cond(test, then, other) => {
+                if self.eval(test)? != 0 {
+                    self.eval(then)?
+                } else {
+                    self.eval(other)?
+                }
+            },
+        })
+    }
+
+    /// Reads a variable, evaluating its contents as an expression.
+    fn var(&mut self, name: &str) -> Result<i64, ArithError> {
+        let text = self.vars.get(name).unwrap_or_default();
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = text.parse() {
+            return Ok(n);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(ArithError::Recursion(name.to_string()));
+        }
+        eval_at(text, self.vars, self.depth + 1)
+    }
+}
+
+/// Applies a binary operator.
+fn apply(op: &str, a: i64, b: i64) -> Result<i64, ArithError> {
+    Ok(match op {
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" | "%" if b == 0 => return Err(ArithError::DivideByZero),
+        "/" => a.wrapping_div(b),
+        "%" => a.wrapping_rem(b),
+        "**" if b < 0 => return Err(ArithError::NegativeExponent),
+        "**" => a.wrapping_pow(b as u32),
+        "<<" => a.wrapping_shl(b as u32),
+        ">>" => a.wrapping_shr(b as u32),
+        "<" => (a < b) as i64,
+        "<=" => (a <= b) as i64,
+        ">" => (a > b) as i64,
+        ">=" => (a >= b) as i64,
+        "==" => (a == b) as i64,
+        "!=" => (a != b) as i64,
+        "&" => a & b,
+        "^" => a ^ b,
+        "|" => a | b,
+        op => return Err(ArithError::Syntax(format!("unknown operator `{}`", op))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    impl Variables for HashMap<String, String> {
+        fn get(&self, name: &str) -> Option<String> {
+            HashMap::get(self, name).cloned()
+        }
+        fn set(&mut self, name: &str, value: String) -> Result<(), ArithError> {
+            self.insert(name.to_string(), value);
+            Ok(())
+        }
+    }
+
+    fn calc(text: &str) -> Result<i64, ArithError> {
+        eval(text, &mut HashMap::new())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(calc("1 + 2 * 3"), Ok(7));
+        assert_eq!(calc("(1 + 2) * 3"), Ok(9));
+        assert_eq!(calc("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(calc("-2 ** 2"), Ok(4));
+        assert_eq!(calc("7 % 4 << 2"), Ok(12));
+        assert_eq!(calc("1 < 2 == 1"), Ok(1));
+        assert_eq!(calc("6 & 3 | 8 ^ 1"), Ok(11));
+        assert_eq!(calc("!0 + ~0"), Ok(0));
+        assert_eq!(calc("1 ? 2 : 3"), Ok(2));
+        assert_eq!(calc("0 ? 2 : 0 ? 3 : 4"), Ok(4));
+        assert_eq!(calc("1, 2, 3"), Ok(3));
+        assert_eq!(calc(""), Ok(0));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(calc("0x1F"), Ok(31));
+        assert_eq!(calc("010"), Ok(8));
+        assert_eq!(calc("2#1010"), Ok(10));
+        assert_eq!(calc("36#z"), Ok(35));
+        assert_eq!(calc("64#_"), Ok(63));
+        assert!(calc("09").is_err());
+        assert!(calc("1#1").is_err());
+    }
+
+    #[test]
+    fn variables() {
+        let mut vars = HashMap::new();
+        vars.insert("x".to_string(), "5".to_string());
+        vars.insert("e".to_string(), "x * 2".to_string());
+        assert_eq!(eval("x + e", &mut vars), Ok(15));
+        assert_eq!(eval("unset + 1", &mut vars), Ok(1));
+
+        assert_eq!(eval("y = x += 2", &mut vars), Ok(7));
+        assert_eq!(vars["x"], "7");
+        assert_eq!(vars["y"], "7");
+
+        assert_eq!(eval("x++", &mut vars), Ok(7));
+        assert_eq!(eval("++x", &mut vars), Ok(9));
+        assert_eq!(eval("x--, x", &mut vars), Ok(8));
+        assert_eq!(eval("x <<= 1", &mut vars), Ok(16));
+
+        vars.insert("loop".to_string(), "loop + 1".to_string());
+        assert!(eval("loop", &mut vars).is_err());
+    }
+
+    #[test]
+    fn short_circuit() {
+        let mut vars = HashMap::new();
+        assert_eq!(eval("0 && (x = 1)", &mut vars), Ok(0));
+        assert_eq!(eval("1 || (x = 1)", &mut vars), Ok(1));
+        assert_eq!(eval("1 ? 2 : (x = 1)", &mut vars), Ok(2));
+        assert!(vars.get("x").is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(calc("1 / 0"), Err(ArithError::DivideByZero));
+        assert_eq!(calc("2 ** -1"), Err(ArithError::NegativeExponent));
+        assert!(calc("1 +").is_err());
+        assert!(calc("(1").is_err());
+        assert!(calc("1 2").is_err());
+        assert!(calc("3++").is_err());
+    }
+}
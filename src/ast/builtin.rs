@@ -1,4 +1,4 @@
-use std::{path::Path, str};
+use std::str;
 use failure::{
     Fail,
 };
@@ -7,33 +7,501 @@ use crate::parse::{Parse, ParseError};
 /// Represents all shell builtins.
 #[derive(Clone, Debug)]
 pub enum Builtin<'a> {
+    /// `alias [name[=value]...]`: define aliases, or print them.
+    Alias(Vec<&'a str>),
+    /// `break [n]`: leave the `n` innermost loops, or one.
+    Break(usize),
     Clear,
-    Cd(&'a Path),
-    Exit,
+    Cd(Cd<'a>),
+    /// `continue [n]`: start the next iteration of the `n`th innermost loop,
+    /// or of the innermost.
+    Continue(usize),
+    /// `declare`, `typeset`, `export`, `readonly`, and `local`: set variables
+    /// and their attributes, or print them.
+    Declare(Declare<'a>),
+    Dirs(Dirs),
+    /// `exit [n]`: leave the shell with status `n`, or with the status of the
+    /// last command.
+    Exit(Option<i32>),
+    Popd(Option<StackIndex>),
+    Pushd(Pushd<'a>),
+    /// `return [n]`: leave the running function with status `n`, or with the
+    /// status of the last command.
+    Return(Option<i32>),
+    /// `set [--] [argument...]`: make the arguments the positional
+    /// parameters, or with no arguments, print every shell variable.
+    Set(Option<Vec<&'a str>>),
+    /// `shift [n]`: drop the first `n` positional parameters, or the first.
+    Shift(usize),
+    Shopt(Shopt<'a>),
+    /// `source file [argument...]`, or `. file [argument...]`: run the
+    /// commands in a file in the shell itself.
+    Source(&'a str, Vec<&'a str>),
+    Unalias(Unalias<'a>),
+    Unset(Unset<'a>),
+    Z(Z<'a>),
+}
+
+/// Arguments to the `cd` builtin.
+///
+/// ```text
+/// cd [-L|-P] [directory]
+/// cd -
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Cd<'a> {
+    /// The directory to change to. With no directory, `cd` goes to `$HOME`,
+    /// and the directory `-` means `$OLDPWD`.
+    pub to: Option<&'a str>,
+    /// Set by `-P` to resolve symbolic links in the new directory. By default
+    /// (`-L`), the path is kept as the user wrote it.
+    pub physical: bool,
+}
+
+/// Arguments to `declare`, and to the builtins that are forms of it.
+///
+/// ```text
+/// declare [-aAgprx] [+x] [name[=value]...]
+/// typeset [-aAgprx] [+x] [name[=value]...]
+/// export [-np] [name[=value]...]
+/// readonly [-p] [name[=value]...]
+/// local [-aA] [name[=value]...]
+/// ```
+///
+/// `export` is `declare -gx`, and `export -n` is `declare -g +x`. `readonly`
+/// is `declare -gr`. Inside a function, `declare` and `typeset` make local
+/// variables, unless `-g` is given, and `local` may only be used there.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Declare<'a> {
+    /// The name the builtin was run as, for its messages.
+    pub builtin: &'static str,
+    /// `Some(true)` for `-x`, to export the variables, and `Some(false)` for
+    /// `+x`, to stop exporting them.
+    pub export: Option<bool>,
+    /// `-r`: make the variables readonly.
+    pub readonly: bool,
+    /// `-a`: make the variables indexed arrays.
+    pub indexed: bool,
+    /// `-A`: make the variables associative arrays.
+    pub assoc: bool,
+    /// `-g`: set global variables, even inside a function.
+    pub global: bool,
+    /// `-p`: print the variables with their attributes, instead of setting
+    /// them. This is also the behavior when no variables are given.
+    pub print: bool,
+    /// The variables, each a name or `name=value`.
+    pub vars: Vec<&'a str>,
+}
+
+/// Arguments to the `pushd` builtin.
+///
+/// ```text
+/// pushd [directory | +N | -N]
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Pushd<'a> {
+    /// With no arguments, exchange the top two directories.
+    Swap,
+    /// Rotate the stack so that the given entry is on top.
+    Rotate(StackIndex),
+    /// Push the working directory and change to a new one.
+    Dir(&'a str),
+}
+
+/// An entry in the directory stack, written `+N` or `-N`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StackIndex {
+    /// `+N`: the Nth entry counting from zero at the top of the stack, which is
+    /// the working directory.
+    Top(usize),
+    /// `-N`: the Nth entry counting from zero at the bottom of the stack.
+    Bottom(usize),
+}
+
+/// Options to the `dirs` builtin.
+///
+/// ```text
+/// dirs [-clpv]
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Dirs {
+    /// `-c`: empty the stack instead of printing it.
+    pub clear: bool,
+    /// `-l`: print full paths, without abbreviating `$HOME` as `~`.
+    pub long: bool,
+    /// `-p`: print one entry per line.
+    pub lines: bool,
+    /// `-v`: print one entry per line, numbered.
+    pub verbose: bool,
+}
+
+/// Arguments to the `shopt` builtin.
+///
+/// ```text
+/// shopt [-s|-u] [name...]
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Shopt<'a> {
+    /// `Some(true)` for `-s`, to set the named options, and `Some(false)` for
+    /// `-u`, to unset them. With neither, the options are listed.
+    pub set: Option<bool>,
+    pub names: Vec<&'a str>,
+}
+
+/// Arguments to the `unalias` builtin.
+///
+/// ```text
+/// unalias -a
+/// unalias name...
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Unalias<'a> {
+    /// `-a`: remove every alias.
+    pub all: bool,
+    pub names: Vec<&'a str>,
+}
+
+/// Arguments to the `unset` builtin.
+///
+/// ```text
+/// unset [-fv] name...
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Unset<'a> {
+    /// `-f`: remove functions, instead of variables (`-v`).
+    pub functions: bool,
+    pub names: Vec<&'a str>,
+}
+
+/// Arguments to the `z` builtin.
+///
+/// ```text
+/// z [-l] [fragment...]
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Z<'a> {
+    /// `-l`: list the matching directories instead of changing to the best.
+    /// This is also the behavior when no fragments are given.
+    pub list: bool,
+    /// Text that the directory path must contain, in order.
+    pub fragments: Vec<&'a str>,
 }
 
 #[derive(Clone, Debug, Fail)]
-pub enum CdError {
-    #[fail(display = "cd: no path provided")]
-    NoPath,
+pub enum BuiltinError {
+    #[fail(display = "{}: {}: invalid option", _0, _1)]
+    InvalidOption(&'static str, String),
+    #[fail(display = "{}: too many arguments", _0)]
+    TooManyArgs(&'static str),
+    #[fail(display = "{}: {}: loop count out of range", _0, _1)]
+    LoopCount(&'static str, String),
+    #[fail(display = "{}: filename argument required", _0)]
+    MissingArg(&'static str),
+    #[fail(display = "{}: {}: numeric argument required", _0, _1)]
+    Numeric(&'static str, String),
+}
+
+// ===== impl StackIndex =====
+
+impl StackIndex {
+    /// Parses `+N` or `-N`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let index = |n: &str| n.parse::<usize>().ok();
+        if text.starts_with('+') {
+            index(&text[1 ..]).map(StackIndex::Top)
+        } else if text.starts_with('-') {
+            index(&text[1 ..]).map(StackIndex::Bottom)
+        } else {
+            None
+        }
+    }
+
+    /// Finds the position this index refers to in a stack of `len` entries.
+    pub fn resolve(self, len: usize) -> Option<usize> {
+        match self {
+            StackIndex::Top(n) if n < len => Some(n),
+            StackIndex::Bottom(n) if n < len => Some(len - 1 - n),
+            _ => None,
+        }
+    }
 }
 
 // ===== impl Builtin =====
 
 impl<'a> Parse<'a> for Builtin<'a> {
-    type Error = CdError;
+    type Error = BuiltinError;
     fn parse_from(text: &'a str) -> Result<Self, ParseError<Self::Error>> {
-        let mut args = super::ArgsIter { text };
+        Self::from_args(super::Words::new(text))
+    }
+}
+
+impl<'a> Builtin<'a> {
+    /// Recognizes a builtin from words that have already been split and
+    /// expanded, the first of which is its name.
+    pub fn from_args(
+        mut args: impl Iterator<Item = &'a str>,
+    ) -> Result<Self, ParseError<BuiltinError>> {
         match args.next().ok_or(ParseError::NoInput)? {
+            "alias" => Ok(Builtin::Alias(args.collect())),
+            "break" => loops("break", args).map(Builtin::Break).map_err(Into::into),
             "clear" => Ok(Builtin::Clear),
-            "cd" => {
-                let path = args.next().ok_or(CdError::NoPath)?;
-                Ok(Builtin::Cd(Path::new(path)))
+            "cd" => cd(args).map(Builtin::Cd).map_err(Into::into),
+            "continue" => loops("continue", args).map(Builtin::Continue).map_err(Into::into),
+            "declare" => declare("declare", args).map(Builtin::Declare).map_err(Into::into),
+            "dirs" => {
+                let mut dirs = Dirs::default();
+                for arg in args {
+                    let flags = match arg {
+                        a if a.starts_with('-') && a.len() > 1 => &a[1 ..],
+                        _ => return Err(BuiltinError::TooManyArgs("dirs").into()),
+                    };
+                    for flag in flags.chars() {
+                        match flag {
+                            'c' => dirs.clear = true,
+                            'l' => dirs.long = true,
+                            'p' => dirs.lines = true,
+                            'v' => dirs.verbose = true,
+                            _ => return Err(BuiltinError::InvalidOption("dirs", arg.to_string()).into()),
+                        }
+                    }
+                }
+                Ok(Builtin::Dirs(dirs))
             },
             "exit" => {
-                Ok(Builtin::Exit)
+                let status = match args.next() {
+                    None => None,
+                    Some(arg) => Some(arg.parse::<i32>().map_err(|_| {
+                        BuiltinError::Numeric("exit", arg.to_string())
+                    })?),
+                };
+                if args.next().is_some() {
+                    return Err(BuiltinError::TooManyArgs("exit").into());
+                }
+                Ok(Builtin::Exit(status))
+            },
+            "export" => declare("export", args).map(Builtin::Declare).map_err(Into::into),
+            "local" => declare("local", args).map(Builtin::Declare).map_err(Into::into),
+            "popd" => {
+                let index = match args.next() {
+                    None => None,
+                    Some(arg) => Some(StackIndex::parse(arg).ok_or_else(|| {
+                        BuiltinError::InvalidOption("popd", arg.to_string())
+                    })?),
+                };
+                if args.next().is_some() {
+                    return Err(BuiltinError::TooManyArgs("popd").into());
+                }
+                Ok(Builtin::Popd(index))
+            },
+            "pushd" => {
+                let pushd = match args.next() {
+                    None => Pushd::Swap,
+                    Some(arg) => StackIndex::parse(arg)
+                        .map(Pushd::Rotate)
+                        .unwrap_or(Pushd::Dir(arg)),
+                };
+                if args.next().is_some() {
+                    return Err(BuiltinError::TooManyArgs("pushd").into());
+                }
+                Ok(Builtin::Pushd(pushd))
+            },
+            "readonly" => declare("readonly", args).map(Builtin::Declare).map_err(Into::into),
+            "return" => {
+                let status = match args.next() {
+                    None => None,
+                    Some(arg) => Some(arg.parse::<i32>().map_err(|_| {
+                        BuiltinError::Numeric("return", arg.to_string())
+                    })?),
+                };
+                if args.next().is_some() {
+                    return Err(BuiltinError::TooManyArgs("return").into());
+                }
+                Ok(Builtin::Return(status))
+            },
+            "set" => {
+                let mut args = args.peekable();
+                match args.peek() {
+                    None => Ok(Builtin::Set(None)),
+                    Some(&"--") => Ok(Builtin::Set(Some(args.skip(1).collect()))),
+                    //  ysh has no shell options for `set` yet.
+                    Some(a) if (a.starts_with('-') || a.starts_with('+')) && a.len() > 1 => {
+                        Err(BuiltinError::InvalidOption("set", a.to_string()).into())
+                    },
+                    Some(_) => Ok(Builtin::Set(Some(args.collect()))),
+                }
+            },
+            "shift" => {
+                let count = match args.next() {
+                    None => 1,
+                    Some(arg) => arg.parse::<usize>().map_err(|_| {
+                        BuiltinError::Numeric("shift", arg.to_string())
+                    })?,
+                };
+                if args.next().is_some() {
+                    return Err(BuiltinError::TooManyArgs("shift").into());
+                }
+                Ok(Builtin::Shift(count))
+            },
+            "shopt" => {
+                let mut shopt = Shopt::default();
+                for arg in args {
+                    match arg {
+                        "-s" => shopt.set = Some(true),
+                        "-u" => shopt.set = Some(false),
+                        a if a.starts_with('-') => {
+                            return Err(BuiltinError::InvalidOption("shopt", a.to_string()).into());
+                        },
+                        a => shopt.names.push(a),
+                    }
+                }
+                Ok(Builtin::Shopt(shopt))
+            },
+            "source" | "." => match args.next() {
+                Some(file) => Ok(Builtin::Source(file, args.collect())),
+                None => Err(BuiltinError::MissingArg("source").into()),
+            },
+            "typeset" => declare("typeset", args).map(Builtin::Declare).map_err(Into::into),
+            "unalias" => {
+                let mut unalias = Unalias::default();
+                for arg in args {
+                    match arg {
+                        "-a" => unalias.all = true,
+                        a if a.starts_with('-') => {
+                            return Err(BuiltinError::InvalidOption("unalias", a.to_string()).into());
+                        },
+                        a => unalias.names.push(a),
+                    }
+                }
+                Ok(Builtin::Unalias(unalias))
+            },
+            "unset" => {
+                let mut unset = Unset::default();
+                for arg in args {
+                    match arg {
+                        "-f" => unset.functions = true,
+                        "-v" => unset.functions = false,
+                        a if a.starts_with('-') => {
+                            return Err(BuiltinError::InvalidOption("unset", a.to_string()).into());
+                        },
+                        a => unset.names.push(a),
+                    }
+                }
+                Ok(Builtin::Unset(unset))
+            },
+            "z" => {
+                let mut z = Z::default();
+                for arg in args {
+                    match arg {
+                        "-l" => z.list = true,
+                        a if a.starts_with('-') => {
+                            return Err(BuiltinError::InvalidOption("z", a.to_string()).into());
+                        },
+                        a => z.fragments.push(a),
+                    }
+                }
+                Ok(Builtin::Z(z))
             },
             _ => Err(ParseError::Unrecognized),
         }
     }
 }
+
+/// Parses the arguments to `cd`.
+fn cd<'a>(args: impl Iterator<Item = &'a str>) -> Result<Cd<'a>, BuiltinError> {
+    let mut cd = Cd::default();
+    let mut args = args.peekable();
+    //  Options come first. `-` alone is a directory, not an option, and `--`
+    //  ends the options.
+    while let Some(opt) = args.peek().filter(|a| a.starts_with('-') && a.len() > 1) {
+        if *opt == "--" {
+            args.next();
+            break;
+        }
+        for flag in opt[1 ..].chars() {
+            match flag {
+                'L' => cd.physical = false,
+                'P' => cd.physical = true,
+                _ => return Err(BuiltinError::InvalidOption("cd", opt.to_string())),
+            }
+        }
+        args.next();
+    }
+    cd.to = args.next();
+    if args.next().is_some() {
+        return Err(BuiltinError::TooManyArgs("cd"));
+    }
+    Ok(cd)
+}
+
+/// Parses the arguments to `declare` or one of its forms, which is named by
+/// `builtin`.
+fn declare<'a>(
+    builtin: &'static str,
+    args: impl Iterator<Item = &'a str>,
+) -> Result<Declare<'a>, BuiltinError> {
+    let mut declare = Declare { builtin, ..Declare::default() };
+    //  Each form allows only some of the options of `declare`.
+    let allowed = match builtin {
+        "export" => {
+            declare.export = Some(true);
+            declare.global = true;
+            "np"
+        },
+        "readonly" => {
+            declare.readonly = true;
+            declare.global = true;
+            "p"
+        },
+        "local" => "aA",
+        _ => "aAgprx",
+    };
+    let mut args = args.peekable();
+    while let Some(opt) = args.peek().filter(|a| {
+        (a.starts_with('-') || a.starts_with('+')) && a.len() > 1
+    }) {
+        if *opt == "--" {
+            args.next();
+            break;
+        }
+        let on = opt.starts_with('-');
+        for flag in opt[1 ..].chars() {
+            match flag {
+                _ if !allowed.contains(flag) => {
+                    return Err(BuiltinError::InvalidOption(builtin, opt.to_string()));
+                },
+                'n' if on => declare.export = Some(false),
+                'p' if on => declare.print = true,
+                'g' if on => declare.global = true,
+                'r' if on => declare.readonly = true,
+                'a' if on => declare.indexed = true,
+                'A' if on => declare.assoc = true,
+                'x' => declare.export = Some(on),
+                _ => return Err(BuiltinError::InvalidOption(builtin, opt.to_string())),
+            }
+        }
+        args.next();
+    }
+    declare.vars = args.collect();
+    if declare.vars.is_empty() {
+        declare.print = true;
+    }
+    Ok(declare)
+}
+
+/// Parses the loop count given to `break` or `continue`.
+fn loops<'a>(
+    name: &'static str,
+    mut args: impl Iterator<Item = &'a str>,
+) -> Result<usize, BuiltinError> {
+    let count = match args.next() {
+        None => 1,
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(BuiltinError::LoopCount(name, arg.to_string())),
+        },
+    };
+    if args.next().is_some() {
+        return Err(BuiltinError::TooManyArgs(name));
+    }
+    Ok(count)
+}
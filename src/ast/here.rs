@@ -0,0 +1,81 @@
+//! Here-documents and here-strings
+//!
+//! `<<word` and `<<-word` give a command the lines after it, up to a line
+//! holding only `word`, as its standard input, and `<<<word` gives it the word
+//! itself. The operators may come anywhere among the words of the command, and
+//! need no whitespace before them when they are not quoted, as in `cat<<EOF`.
+//! The lines of a here-document are kept with the command's own line, so that
+//! a simple command is all the text that it needs to run.
+
+use crate::token;
+
+/// Text given to a command as its standard input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Here<'a> {
+    /// A here-document, `<<word` or `<<-word`: the lines after the command, up
+    /// to a line holding only `word`.
+    Doc {
+        /// The lines of the document, with their leading tabs removed for
+        /// `<<-`.
+        body: String,
+        /// Whether expansions in the body are performed. Quoting any part of
+        /// the delimiter word turns them off.
+        expand: bool,
+    },
+    /// A here-string, `<<<word`: the word, expanded, and a newline.
+    Str(&'a str),
+}
+
+/// Splits a command from its here-documents.
+///
+/// The command is the first line of the text. Each here-document or
+/// here-string on that line is returned in order, with the bodies of the
+/// here-documents taken from the following lines. A body that never reaches
+/// its delimiter runs to the end of the text.
+pub fn split(text: &str) -> Result<(&str, Vec<Here>), String> {
+    let (mut bodies, line) = token::line(text)
+        .map_err(|_| "unterminated quote".to_string())?;
+    let mut found = Vec::new();
+    let mut rem = line;
+    while !rem.trim().is_empty() {
+        if let Ok((rest, (op, word))) = token::trim_left(token::here_op)(rem) {
+            rem = rest;
+            if op == "<<<" {
+                found.push(Here::Str(word));
+                continue;
+            }
+            let strip = op == "<<-";
+            let (after, body) = token::here_body(bodies, word, strip)
+                .unwrap_or(("", bodies));
+            bodies = after;
+            let body = if strip {
+                body.lines()
+                    .map(|l| format!("{}\n", l.trim_start_matches('\t')))
+                    .collect()
+            } else {
+                body.to_string()
+            };
+            let expand = !word.contains(|c| c == '\'' || c == '"' || c == '\\');
+            found.push(Here::Doc { body, expand });
+            continue;
+        }
+        let start = rem.trim_start();
+        if start.starts_with("<<") {
+            return Err(format!("syntax error: `{}` needs a word", start));
+        }
+        rem = match token::trim_left(token::command_word)(rem) {
+            Ok((rest, _)) => rest,
+            Err(_) => break,
+        };
+    }
+    Ok((line, found))
+}
+
+/// Skips any here-document and here-string operators, with their words, at the
+/// start of the text.
+crate fn skip(mut text: &str) -> &str {
+    while let Ok((rest, _)) = token::trim_left(token::here_op)(text) {
+        text = rest;
+    }
+    text
+}
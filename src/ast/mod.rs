@@ -1,127 +1,87 @@
 use std::str;
 
-use crate::parse::{Parse, ParseError};
 use crate::token;
-use crate::env::EnvIter;
 pub mod builtin;
-mod invoke;
+pub mod here;
+pub mod script;
 
-pub use self::builtin::Builtin;
-pub use self::invoke::Invoke;
+pub use self::builtin::{Builtin, Cd, Declare, Dirs, Pushd, Shopt, StackIndex, Unalias, Unset, Z};
+pub use self::here::Here;
+pub use self::script::{CaseArm, Command, Script, SyntaxError};
 
+/// Iterates over the shell words of a text. See `token::shell_word`.
 #[derive(Debug, Clone)]
-pub struct ArgsIter<'a> {
+pub struct Words<'a> {
     text: &'a str
 }
 
-impl<'a> std::iter::Iterator for ArgsIter<'a> {
-    type Item = &'a str;
-    fn next(&mut self) -> Option<Self::Item> {
-        //  Use the tokenizer to get a snippet
-        let (rest, span) = token::trim_left(token::atom)(self.text)
-            //  Suppress the errors for now. May be worth investigating so that
-            //  the shell can report invalid syntax?
-            .ok()?;
-        self.text = rest;
-        Some(span)
-    }
-}
-
-#[derive(Clone, Debug)]
-pub enum Cmd<'a> {
-    Builtin(builtin::Builtin<'a>),
-    Invoke(Invoke<'a>),
-}
-
-impl<'a> Parse<'a> for Cmd<'a> {
-    type Error = String; // placeholder
-    fn parse_from(s: &'a str) -> Result<Self, ParseError<Self::Error>> {
-        Builtin::parse_from(s)
-            .map(Cmd::Builtin)
-            .or_else(|_| Invoke::parse_from(s).map(Cmd::Invoke))
+impl<'a> Words<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Words { text }
     }
 }
 
-/// Evaluate a command in an environment.
-///
-/// This represents forms such as
-///
-/// ```text
-/// FOO=foo BAR=bar command
-/// ```
-///
-/// and
-///
-/// ```text
-/// env FOO=foo BAR=bar command
-/// ```
-#[derive(Clone, Debug)]
-pub struct WithEnv<'a> {
-    crate env: EnvIter<'a>,
-    crate cmd: Cmd<'a>,
-}
-
-impl<'a> Parse<'a> for WithEnv<'a> {
-    type Error = String; // placeholder
-    fn parse_from(s: &'a str) -> Result<Self, ParseError<Self::Error>> {
-        let env = EnvIter::new(s);
-        //  fast-forward an EnvIter over the string, until it runs out of env
-        //  vars
-        let mut ei = env.clone();
-        ei.by_ref().for_each(drop);
-        //  get the fast-forwarded text
-        let EnvIter { text } = ei;
-        let cmd_str = text;
-        let cmd = Cmd::parse_from(cmd_str)?;
-        Ok(Self { env, cmd })
+impl<'a> std::iter::Iterator for Words<'a> {
+    type Item = &'a str;
+    fn next(&mut self) -> Option<Self::Item> {
+        let text = here::skip(self.text);
+        if token::trim_left(token::comment)(text).is_ok() {
+            return None;
+        }
+        let (rest, word) = token::trim_left(token::shell_word)(text).ok()?;
+        self.text = rest;
+        Some(word)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::env::EnvVar;
+    use crate::parse::{Parse, ParseError};
 
     #[test]
-    fn without_env() {
-        let text = r#"command argument "complex argument""#;
-        let with_env = WithEnv::parse_from(text).expect("source is correct");
-
-        let WithEnv { env, cmd } = with_env;
-        assert_eq!(env.count(), 0);
-        match cmd {
-            Cmd::Builtin(_) => panic!("'command' is not a builtin"),
-            Cmd::Invoke(Invoke { command, args }) => {
-                assert_eq!(command, "command");
-                assert_eq!(args.clone().count(), 2);
-                assert_eq!(args.collect::<Vec<_>>(), &["argument", "complex argument"]);
-            },
-        }
+    fn cd_options() {
+        let cd = |text| match Builtin::parse_from(text) {
+            Ok(Builtin::Cd(cd)) => cd,
+            other => panic!("{} is cd, not {:?}", text, other),
+        };
+        assert_eq!(cd("cd"), Cd { to: None, physical: false });
+        assert_eq!(cd("cd -"), Cd { to: Some("-"), physical: false });
+        assert_eq!(cd("cd -P /tmp"), Cd { to: Some("/tmp"), physical: true });
+        assert_eq!(cd("cd -PL -- -x"), Cd { to: Some("-x"), physical: false });
+        assert!(Builtin::parse_from("cd -Q").is_err());
+        assert!(Builtin::parse_from("cd a b").is_err());
     }
 
     #[test]
-    fn with_env() {
-        use crate::env::EnvVar;
-        let text = r#"TEST=1 AUTHOR=myrrlyn cd 'complex path'"#;
-        let with_env = WithEnv::parse_from(text).expect("source is correct");
+    fn declare_options() {
+        let declare = |text| match Builtin::parse_from(text) {
+            Ok(Builtin::Declare(declare)) => declare,
+            other => panic!("{} is declare, not {:?}", text, other),
+        };
+        let export = declare("export -n a=1 b");
+        assert_eq!((export.export, export.global, export.print), (Some(false), true, false));
+        assert_eq!(export.vars, vec!["a=1", "b"]);
+        let typeset = declare("typeset -xr +x -- -a");
+        assert_eq!((typeset.export, typeset.readonly, typeset.global), (Some(false), true, false));
+        assert_eq!(typeset.vars, vec!["-a"]);
+        assert!(declare("readonly").print);
+        assert!(Builtin::parse_from("readonly -x a").is_err());
+        assert!(Builtin::parse_from("local -g a").is_err());
+        let local = declare("local -aA m");
+        assert_eq!((local.indexed, local.assoc, local.global), (true, true, false));
+        assert!(Builtin::parse_from("export -a a").is_err());
+    }
 
-        let WithEnv { env, cmd } = with_env;
-        assert_eq!(env.clone().count(), 2);
-        assert_eq!(
-            env.collect::<Vec<_>>(),
-            vec![("TEST", "1"), ("AUTHOR", "myrrlyn")].into_iter()
-                .map(EnvVar::from)
-                .collect::<Vec<_>>()
-        );
-        match cmd {
-            Cmd::Invoke(_) => panic!("'cd' is a builtin"),
-            Cmd::Builtin(Builtin::Cd(path)) => {
-                assert_eq!(
-                    path.to_str().expect("source is valid str"),
-                    "complex path",
-                );
-            },
-            Cmd::Builtin(_) => panic!("'cd' is only the builtin 'Cd'"),
-        }
+    #[test]
+    fn comments() {
+        let words = |text| Words::new(text).collect::<Vec<_>>();
+        assert_eq!(words("ls -l # list 'files'"), vec!["ls", "-l"]);
+        assert_eq!(words("echo a#b '#c'"), vec!["echo", "a#b", "'#c'"]);
+        assert!(words("  # nothing to run").is_empty());
+        assert!(match Builtin::parse_from("  # nothing to run") {
+            Err(ParseError::NoInput) => true,
+            _ => false,
+        });
     }
 }
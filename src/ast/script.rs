@@ -0,0 +1,643 @@
+//! Scripts: lists of commands, including compound commands.
+//!
+//! A `Script` owns its contents, rather than borrowing the text it was parsed
+//! from. A compound command may span many lines of input, and the
+//! commands inside a loop run many times, so each simple command is kept as
+//! text and expanded every time it runs.
+//!
+//! ```text
+//! if list; then list; [elif list; then list;]... [else list;] fi
+//! while list; do list; done
+//! until list; do list; done
+//! for name [in word...]; do list; done
+//! case word in [(]pattern[|pattern]...) list;; ... esac
+//! ( list )
+//! { list; }
+//! name() { list; }
+//! function name [()] { list; }
+//! ```
+//!
+//! Commands in a list are separated by `;` or by newlines. The reserved words
+//! are only recognized as the first word of a command, so `echo fi` is a
+//! simple command. Here-documents and here-strings after a subshell or a group
+//! are the input of all the commands in it.
+
+use std::rc::Rc;
+
+use failure::Fail;
+
+use crate::parse::{Parse, ParseError};
+use crate::token;
+
+/// A list of commands, run one after another.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Script {
+    pub commands: Vec<Command>,
+}
+
+/// A command in a script.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// A simple command, as written. The text holds the command's line and,
+    /// after a newline, the bodies of its here-documents.
+    Simple(String),
+    /// Runs the body of the first branch whose test succeeds, or else the
+    /// `else` body, if any.
+    If {
+        branches: Vec<(Vec<Command>, Vec<Command>)>,
+        otherwise: Option<Vec<Command>>,
+    },
+    /// Runs the body while the test succeeds, or for `until`, while it fails.
+    While {
+        until: bool,
+        test: Vec<Command>,
+        body: Vec<Command>,
+    },
+    /// Runs the body once for each word, with the variable `name` set to it.
+    /// Without a word list, the loop is over the positional parameters.
+    For {
+        name: String,
+        words: Option<Vec<String>>,
+        body: Vec<Command>,
+    },
+    /// Runs the body of the first arm with a pattern that matches the word.
+    Case {
+        word: String,
+        arms: Vec<CaseArm>,
+    },
+    /// Runs the body in a copy of the shell, so that it cannot change the
+    /// working directory, the variables, or anything else in the shell itself.
+    Subshell {
+        body: Vec<Command>,
+        /// The here-documents and here-strings for the whole body, written as
+        /// for a simple command with no words.
+        redirects: String,
+    },
+    /// Runs the body in the shell itself, as one command.
+    Group {
+        body: Vec<Command>,
+        redirects: String,
+    },
+    /// Defines a function, which runs its body when it is invoked like a
+    /// command. The body is shared with the function table, rather than copied
+    /// into it, every time the definition runs.
+    Function {
+        name: String,
+        body: Rc<Vec<Command>>,
+    },
+}
+
+/// One arm of a `case` command.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CaseArm {
+    pub patterns: Vec<String>,
+    pub body: Vec<Command>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Fail)]
+pub enum SyntaxError {
+    /// The input ends in the middle of a command. More lines may complete it.
+    #[fail(display = "syntax error: unexpected end of input")]
+    Incomplete,
+    #[fail(display = "syntax error near unexpected token `{}`", _0)]
+    Unexpected(String),
+    #[fail(display = "syntax error: `{}` is not a valid name", _0)]
+    BadName(String),
+    #[fail(display = "syntax error: a command with a here-document must end its line")]
+    HereDoc,
+}
+
+/// Words that begin or end parts of compound commands.
+const RESERVED: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "while", "until", "do", "done", "for",
+    "case", "esac", "function", "{", "}",
+];
+
+// ===== impl Script =====
+
+impl<'a> Parse<'a> for Script {
+    type Error = SyntaxError;
+    fn parse_from(text: &'a str) -> Result<Self, ParseError<Self::Error>> {
+        let mut parser = Parser { text, subshells: 0 };
+        let commands = parser.list(&[])?;
+        if commands.is_empty() {
+            return Err(ParseError::NoInput);
+        }
+        Ok(Script { commands })
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    /// How many subshells the parser is in, where `)` ends a command.
+    subshells: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Skips whitespace, comments, and `;` separators, but not `;;`.
+    fn separators(&mut self) {
+        loop {
+            let text = self.text.trim_start();
+            self.text = if text.starts_with(';') && !text.starts_with(";;") {
+                &text[1 ..]
+            } else if let Ok((rest, _)) = token::comment(text) {
+                rest
+            } else {
+                self.text = text;
+                return;
+            };
+        }
+    }
+
+    /// Finds the reserved word at the start of the next command, if any.
+    fn reserved(&self) -> Option<&'static str> {
+        let text = self.text.trim_start();
+        RESERVED.iter().cloned().find(|word| {
+            text.starts_with(word) && text[word.len() ..].chars()
+                .next()
+                .map_or(true, |c| c.is_whitespace() || c == ';' || c == ')')
+        })
+    }
+
+    /// Consumes the reserved word `word`, which must come next.
+    fn expect(&mut self, word: &'static str) -> Result<(), SyntaxError> {
+        self.separators();
+        if self.reserved() == Some(word) {
+            self.text = &self.text.trim_start()[word.len() ..];
+            return Ok(());
+        }
+        match self.next_word() {
+            Some(found) => Err(SyntaxError::Unexpected(found.to_string())),
+            None => Err(SyntaxError::Incomplete),
+        }
+    }
+
+    /// Takes the next shell word, without regard for separators.
+    fn next_word(&mut self) -> Option<&'a str> {
+        let (rest, word) = token::trim_left(token::shell_word)(self.text).ok()?;
+        self.text = rest;
+        Some(word)
+    }
+
+    /// Parses commands until one of the reserved words `ends`, or `;;` or `)`
+    /// if that is among them, or the end of the input when `ends` is empty.
+    fn list(&mut self, ends: &[&str]) -> Result<Vec<Command>, SyntaxError> {
+        let mut commands = Vec::new();
+        loop {
+            self.separators();
+            if self.text.is_empty() {
+                return if ends.is_empty() {
+                    Ok(commands)
+                } else {
+                    Err(SyntaxError::Incomplete)
+                };
+            }
+            if self.text.starts_with(";;") {
+                return if ends.contains(&";;") {
+                    Ok(commands)
+                } else {
+                    Err(SyntaxError::Unexpected(";;".to_string()))
+                };
+            }
+            if self.text.starts_with(')') {
+                return if ends.contains(&")") {
+                    Ok(commands)
+                } else {
+                    Err(SyntaxError::Unexpected(")".to_string()))
+                };
+            }
+            if self.at_subshell() {
+                commands.push(self.subshell()?);
+                continue;
+            }
+            commands.push(match self.reserved() {
+                Some(word) if ends.contains(&word) => return Ok(commands),
+                Some("if") => self.if_command()?,
+                Some("while") => self.while_command(false)?,
+                Some("until") => self.while_command(true)?,
+                Some("for") => self.for_command()?,
+                Some("case") => self.case_command()?,
+                Some("function") => self.function_command()?,
+                Some("{") => {
+                    let body = self.group()?;
+                    Command::Group { body, redirects: self.redirects()? }
+                },
+                Some(word) => return Err(SyntaxError::Unexpected(word.to_string())),
+                None => match self.definition() {
+                    Some((name, rest)) => {
+                        self.text = rest;
+                        self.function_body(name)?
+                    },
+                    None => self.simple()?,
+                },
+            });
+        }
+    }
+
+    fn simple(&mut self) -> Result<Command, SyntaxError> {
+        self.line().map(Command::Simple)
+    }
+
+    /// Takes the rest of a command's line, and the bodies of its
+    /// here-documents from the lines after it.
+    fn line(&mut self) -> Result<String, SyntaxError> {
+        let incomplete = |_| SyntaxError::Incomplete;
+        let (rest, line) = if self.subshells > 0 {
+            token::subshell_command(self.text)
+        } else {
+            token::command(self.text)
+        }.map_err(incomplete)?;
+        let line = line.trim_end();
+        //  The bodies of here-documents begin on the line after the command.
+        let (next_line, tail) = token::line(rest).map_err(incomplete)?;
+        let (after, bodies) = token::here_bodies(line, next_line).map_err(incomplete)?;
+        if bodies.is_empty() {
+            self.text = rest;
+            return Ok(line.to_string());
+        }
+        if !tail.trim().is_empty() {
+            return Err(SyntaxError::HereDoc);
+        }
+        self.text = after;
+        Ok(format!("{}\n{}", line, bodies))
+    }
+
+    /// Parses the redirections after a subshell or a group, which may only be
+    /// here-documents and here-strings.
+    fn redirects(&mut self) -> Result<String, SyntaxError> {
+        let text = self.text.trim_start_matches(|c| c == ' ' || c == '\t');
+        if !text.starts_with('<') {
+            return match text.chars().next() {
+                None | Some(';') | Some('\n') | Some(')') | Some('#') => Ok(String::new()),
+                Some(_) => Err(SyntaxError::Unexpected(self.next_word().unwrap_or(text).to_string())),
+            };
+        }
+        self.text = text;
+        let redirects = self.line()?;
+        let words = super::here::skip(redirects.lines().next().unwrap_or(""));
+        match super::Words::new(words).next() {
+            Some(word) => Err(SyntaxError::Unexpected(word.to_string())),
+            None => Ok(redirects),
+        }
+    }
+
+    /// Checks whether a subshell comes next. `((` begins an arithmetic command
+    /// instead, unless it cannot be one.
+    fn at_subshell(&self) -> bool {
+        self.text.starts_with('(') && match token::arith(self.text) {
+            Err(nom::Err::Error(_)) | Err(nom::Err::Failure(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn subshell(&mut self) -> Result<Command, SyntaxError> {
+        self.text = &self.text[1 ..];
+        self.subshells += 1;
+        let body = self.list(&[")"]);
+        self.subshells -= 1;
+        let body = body?;
+        if body.is_empty() {
+            return Err(SyntaxError::Unexpected(")".to_string()));
+        }
+        self.text = &self.text[1 ..];
+        Ok(Command::Subshell { body, redirects: self.redirects()? })
+    }
+
+    /// Parses a `{ list; }` group, without its redirections.
+    fn group(&mut self) -> Result<Vec<Command>, SyntaxError> {
+        self.expect("{")?;
+        let body = self.list(&["}"])?;
+        self.expect("}")?;
+        Ok(body)
+    }
+
+    fn if_command(&mut self) -> Result<Command, SyntaxError> {
+        self.expect("if")?;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let test = self.list(&["then"])?;
+            self.expect("then")?;
+            let body = self.list(&["elif", "else", "fi"])?;
+            branches.push((test, body));
+            match self.reserved() {
+                Some("elif") => self.expect("elif")?,
+                Some("else") => {
+                    self.expect("else")?;
+                    otherwise = Some(self.list(&["fi"])?);
+                    break;
+                },
+                _ => break,
+            }
+        }
+        self.expect("fi")?;
+        Ok(Command::If { branches, otherwise })
+    }
+
+    fn while_command(&mut self, until: bool) -> Result<Command, SyntaxError> {
+        self.expect(if until { "until" } else { "while" })?;
+        let test = self.list(&["do"])?;
+        self.expect("do")?;
+        let body = self.list(&["done"])?;
+        self.expect("done")?;
+        Ok(Command::While { until, test, body })
+    }
+
+    fn for_command(&mut self) -> Result<Command, SyntaxError> {
+        self.expect("for")?;
+        let name = self.next_word().ok_or(SyntaxError::Incomplete)?;
+        if !is_name(name) {
+            return Err(SyntaxError::BadName(name.to_string()));
+        }
+        self.separators();
+        let words = if self.text.starts_with("in") && self.text[2 ..].chars()
+            .next()
+            .map_or(true, |c| c.is_whitespace() || c == ';')
+        {
+            //  The words run to the end of the line, or to a `;`.
+            let (rest, list) = token::command(&self.text[2 ..])
+                .map_err(|_| SyntaxError::Incomplete)?;
+            self.text = rest;
+            let words = super::Words { text: list };
+            Some(words.map(str::to_string).collect())
+        } else {
+            None
+        };
+        self.expect("do")?;
+        let body = self.list(&["done"])?;
+        self.expect("done")?;
+        Ok(Command::For { name: name.to_string(), words, body })
+    }
+
+    fn case_command(&mut self) -> Result<Command, SyntaxError> {
+        self.expect("case")?;
+        let word = self.next_word().ok_or(SyntaxError::Incomplete)?.to_string();
+        self.separators();
+        match self.next_word() {
+            Some("in") => {},
+            Some(other) => return Err(SyntaxError::Unexpected(other.to_string())),
+            None => return Err(SyntaxError::Incomplete),
+        }
+        let mut arms = Vec::new();
+        loop {
+            self.separators();
+            if self.reserved() == Some("esac") {
+                break;
+            }
+            let patterns = self.patterns()?;
+            let body = self.list(&[";;", "esac"])?;
+            arms.push(CaseArm { patterns, body });
+            if self.text.starts_with(";;") {
+                self.text = &self.text[2 ..];
+            }
+        }
+        self.expect("esac")?;
+        Ok(Command::Case { word, arms })
+    }
+
+    /// Finds `name()` at the start of the next command, which begins a function
+    /// definition. Returns the name and the text after the parentheses.
+    fn definition(&self) -> Option<(&'a str, &'a str)> {
+        let blank = |c| c == ' ' || c == '\t';
+        let text = self.text.trim_start();
+        let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(text.len());
+        let (name, rest) = text.split_at(end);
+        let rest = rest.trim_start_matches(blank);
+        if !is_name(name) || !rest.starts_with('(') {
+            return None;
+        }
+        let rest = rest[1 ..].trim_start_matches(blank);
+        if !rest.starts_with(')') {
+            return None;
+        }
+        Some((name, &rest[1 ..]))
+    }
+
+    fn function_command(&mut self) -> Result<Command, SyntaxError> {
+        self.expect("function")?;
+        let name = self.next_word().ok_or(SyntaxError::Incomplete)?;
+        //  The parentheses are optional after `function name`.
+        let rest = self.text.trim_start_matches(|c| c == ' ' || c == '\t');
+        if rest.starts_with('(') {
+            let rest = rest[1 ..].trim_start_matches(|c| c == ' ' || c == '\t');
+            if !rest.starts_with(')') {
+                return Err(SyntaxError::Unexpected("(".to_string()));
+            }
+            self.text = &rest[1 ..];
+        }
+        self.function_body(name)
+    }
+
+    /// Parses the `{ list; }` body of the function `name`.
+    fn function_body(&mut self, name: &str) -> Result<Command, SyntaxError> {
+        if !is_name(name) {
+            return Err(SyntaxError::BadName(name.to_string()));
+        }
+        let body = self.group()?;
+        Ok(Command::Function { name: name.to_string(), body: Rc::new(body) })
+    }
+
+    /// Parses the patterns of a `case` arm, up to and including the `)`.
+    fn patterns(&mut self) -> Result<Vec<String>, SyntaxError> {
+        if self.text.is_empty() {
+            return Err(SyntaxError::Incomplete);
+        }
+        if self.text.starts_with('(') {
+            self.text = &self.text[1 ..];
+        }
+        let mut patterns = Vec::new();
+        loop {
+            let (rest, pattern) = token::trim_left(token::pattern)(self.text)
+                .map_err(|_| SyntaxError::Incomplete)?;
+            let rest = rest.trim_start();
+            if pattern.is_empty() {
+                return match rest.chars().next() {
+                    Some(c) => Err(SyntaxError::Unexpected(c.to_string())),
+                    None => Err(SyntaxError::Incomplete),
+                };
+            }
+            patterns.push(pattern.to_string());
+            match rest.chars().next() {
+                Some('|') => self.text = &rest[1 ..],
+                Some(')') => {
+                    self.text = &rest[1 ..];
+                    return Ok(patterns);
+                },
+                Some(_) => {
+                    let (_, word) = token::shell_word(rest).unwrap_or((rest, rest));
+                    return Err(SyntaxError::Unexpected(word.to_string()));
+                },
+                None => return Err(SyntaxError::Incomplete),
+            }
+        }
+    }
+}
+
+/// Checks that a variable name is letters, digits, and underscores, and does
+/// not begin with a digit.
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Vec<Command> {
+        Script::parse_from(text).expect("the script is valid").commands
+    }
+
+    fn simple(text: &str) -> Command {
+        Command::Simple(text.to_string())
+    }
+
+    fn error(text: &str) -> SyntaxError {
+        match Script::parse_from(text) {
+            Err(ParseError::Other(e)) => e,
+            other => panic!("{:?} is not a syntax error, but {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(parse("a; b\n c # comment\n"), vec![simple("a"), simple("b"), simple("c")]);
+        assert_eq!(parse("echo fi; echo 'x;y'"), vec![simple("echo fi"), simple("echo 'x;y'")]);
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(parse("if a; then b; elif c\nthen d\nelse e; fi"), vec![Command::If {
+            branches: vec![
+                (vec![simple("a")], vec![simple("b")]),
+                (vec![simple("c")], vec![simple("d")]),
+            ],
+            otherwise: Some(vec![simple("e")]),
+        }]);
+        assert_eq!(parse("until a; do b; done; c"), vec![
+            Command::While { until: true, test: vec![simple("a")], body: vec![simple("b")] },
+            simple("c"),
+        ]);
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(parse("for x in a 'b c' *.rs; do echo $x; done"), vec![Command::For {
+            name: "x".into(),
+            words: Some(vec!["a".into(), "'b c'".into(), "*.rs".into()]),
+            body: vec![simple("echo $x")],
+        }]);
+        assert_eq!(parse("for x\ndo\n  while a; do break 2; done\ndone"), vec![Command::For {
+            name: "x".into(),
+            words: None,
+            body: vec![Command::While {
+                until: false,
+                test: vec![simple("a")],
+                body: vec![simple("break 2")],
+            }],
+        }]);
+    }
+
+    #[test]
+    fn cases() {
+        assert_eq!(parse("case $x in\n  (*.rs|'a b') one;;\n  *) two; three\nesac"), vec![
+            Command::Case {
+                word: "$x".into(),
+                arms: vec![
+                    CaseArm { patterns: vec!["*.rs".into(), "'a b'".into()], body: vec![simple("one")] },
+                    CaseArm { patterns: vec!["*".into()], body: vec![simple("two"), simple("three")] },
+                ],
+            },
+        ]);
+        assert_eq!(parse("case x in esac"), vec![Command::Case { word: "x".into(), arms: vec![] }]);
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(parse("(cd /tmp; ls) ; { echo a; echo ')'; }"), vec![
+            Command::Subshell {
+                body: vec![simple("cd /tmp"), simple("ls")],
+                redirects: String::new(),
+            },
+            Command::Group {
+                body: vec![simple("echo a"), simple("echo ')'")],
+                redirects: String::new(),
+            },
+        ]);
+        assert_eq!(parse("((a) ; (if b; then c; fi))"), vec![Command::Subshell {
+            body: vec![
+                Command::Subshell { body: vec![simple("a")], redirects: String::new() },
+                Command::Subshell {
+                    body: vec![Command::If {
+                        branches: vec![(vec![simple("b")], vec![simple("c")])],
+                        otherwise: None,
+                    }],
+                    redirects: String::new(),
+                },
+            ],
+            redirects: String::new(),
+        }]);
+        assert_eq!(parse("{ read a; cat; } <<EOF\nx\nEOF\n"), vec![Command::Group {
+            body: vec![simple("read a"), simple("cat")],
+            redirects: "<<EOF\nx\nEOF\n".into(),
+        }]);
+        assert_eq!(parse("((x = 1))"), vec![simple("((x = 1))")]);
+    }
+
+    #[test]
+    fn functions() {
+        let function = |name: &str, body| Command::Function { name: name.into(), body: Rc::new(body) };
+        assert_eq!(parse("greet() { echo hi $1; }; greet you"), vec![
+            function("greet", vec![simple("echo hi $1")]),
+            simple("greet you"),
+        ]);
+        assert_eq!(parse("function f\n{\n  local x=1\n  return\n}"), vec![
+            function("f", vec![simple("local x=1"), simple("return")]),
+        ]);
+        assert_eq!(parse("function g () { if a; then b; fi; }"), vec![
+            function("g", vec![Command::If {
+                branches: vec![(vec![simple("a")], vec![simple("b")])],
+                otherwise: None,
+            }]),
+        ]);
+        assert_eq!(parse("echo(x)"), vec![simple("echo(x)")]);
+    }
+
+    #[test]
+    fn here_docs() {
+        assert_eq!(
+            parse("if a; then\n  cat <<EOF\nfi\nEOF\nfi"),
+            vec![Command::If {
+                branches: vec![(vec![simple("a")], vec![simple("cat <<EOF\nfi\nEOF\n")])],
+                otherwise: None,
+            }],
+        );
+        assert_eq!(
+            parse("cat<<EOF\nx\nEOF\ncat file<<-A\n\tA\nnext"),
+            vec![simple("cat<<EOF\nx\nEOF\n"), simple("cat file<<-A\n\tA\n"), simple("next")],
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("if a; then b"), SyntaxError::Incomplete);
+        assert_eq!(error("while a; do 'b"), SyntaxError::Incomplete);
+        assert_eq!(error("cat <<EOF\nbody"), SyntaxError::Incomplete);
+        assert_eq!(error("fi"), SyntaxError::Unexpected("fi".into()));
+        assert_eq!(error("if a; b; fi"), SyntaxError::Unexpected("fi".into()));
+        assert_eq!(error("a;;"), SyntaxError::Unexpected(";;".into()));
+        assert_eq!(error("for 1x in a; do b; done"), SyntaxError::BadName("1x".into()));
+        assert_eq!(error("cat <<EOF; x\nEOF\n"), SyntaxError::HereDoc);
+        assert_eq!(error("f() { a; "), SyntaxError::Incomplete);
+        assert_eq!(error("f() { a }"), SyntaxError::Incomplete);
+        assert_eq!(error("f() a"), SyntaxError::Unexpected("a".into()));
+        assert_eq!(error("function 1x { a; }"), SyntaxError::BadName("1x".into()));
+        assert_eq!(error("}"), SyntaxError::Unexpected("}".into()));
+        assert_eq!(error("(a"), SyntaxError::Incomplete);
+        assert_eq!(error("()"), SyntaxError::Unexpected(")".into()));
+        assert_eq!(error("(a) b"), SyntaxError::Unexpected("b".into()));
+        assert_eq!(error("{ a; } <<<x y"), SyntaxError::Unexpected("y".into()));
+    }
+}
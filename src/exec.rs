@@ -0,0 +1,1440 @@
+//! Running commands
+//!
+//! Scripts are run one command at a time. Simple commands are expanded when
+//! they run, starting with any alias in place of the first word, and the first
+//! word then names a builtin, which changes the `State` of the shell, a
+//! function, or else a program to invoke. Compound commands decide
+//! what to run from the exit statuses of the commands in them.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crossterm::Screen;
+use duct::cmd;
+use failure::{bail, err_msg, Error};
+
+use crate::{
+    ast::{here, script::is_name, Builtin, CaseArm, Command, Declare, Dirs, Here, Pushd, Script, Words},
+    expand::{self, glob, process},
+    parse::{Parse, ParseError},
+    st::{self, State, Subscript, Value, Var},
+    term::Term,
+    token,
+};
+
+/// How deeply functions may call each other before the shell gives up, rather
+/// than overflow its stack.
+const MAX_CALLS: usize = 256;
+
+/// The builtins whose arguments may be assignments.
+const DECLARATIONS: &[&str] = &["declare", "export", "local", "readonly", "typeset"];
+
+/// What the shell does after running a command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Flow {
+    /// Go on to the next command.
+    Next,
+    /// `break N`: leave the N innermost loops.
+    Break(usize),
+    /// `continue N`: leave the N - 1 innermost loops, and start the next
+    /// iteration of the loop around them.
+    Continue(usize),
+    /// `return`: leave the running function.
+    Return,
+    /// A job was interrupted by Ctrl-C, which stops everything that is
+    /// running, including the loops around it.
+    Interrupt,
+    /// `exit`: leave the shell.
+    Exit,
+}
+
+impl State {
+    /// Runs a list of commands, stopping early for anything but `Flow::Next`.
+    ///
+    /// Ctrl-C stops the commands before the next one runs, even when none of
+    /// them start a job for it to interrupt, as in a loop of builtins.
+    pub fn exec(&mut self, commands: &[Command], screen: &mut Screen) -> Result<Flow, Error> {
+        for command in commands {
+            if st::take_interrupt() {
+                self.status = 130;
+                return Ok(Flow::Interrupt);
+            }
+            let flow = self.exec_command(command, screen)?;
+            if flow != Flow::Next {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec_command(&mut self, command: &Command, screen: &mut Screen) -> Result<Flow, Error> {
+        match command {
+            Command::Simple(text) => self.exec_simple(text, screen),
+            Command::If { branches, otherwise } => {
+                for (test, body) in branches {
+                    let flow = self.exec(test, screen)?;
+                    if flow != Flow::Next {
+                        return Ok(flow);
+                    }
+                    if self.status == 0 {
+                        return self.exec(body, screen);
+                    }
+                }
+                match otherwise {
+                    Some(body) => self.exec(body, screen),
+                    None => {
+                        self.status = 0;
+                        Ok(Flow::Next)
+                    },
+                }
+            },
+            Command::While { until, test, body } => self.looping(|this| {
+                //  The status of a loop is that of the last command in its
+                //  body, or zero if the body never ran.
+                let mut status = 0;
+                loop {
+                    if let Some(flow) = lap(this.exec(test, screen)?) {
+                        return Ok(flow);
+                    }
+                    if (this.status == 0) == *until {
+                        break;
+                    }
+                    let flow = this.exec(body, screen)?;
+                    status = this.status;
+                    if let Some(flow) = lap(flow) {
+                        return Ok(flow);
+                    }
+                }
+                this.status = status;
+                Ok(Flow::Next)
+            }),
+            Command::For { name, words, body } => {
+                let words = match words {
+                    Some(words) => expand::words(words.iter().map(String::as_str), self),
+                    //  Without a word list, `for` loops over the positional
+                    //  parameters.
+                    None => Ok(self.args.clone()),
+                };
+                let words = match words {
+                    Ok(words) => words,
+                    Err(e) => {
+                        self.status = 1;
+                        screen.error("ysh", e)?;
+                        return Ok(Flow::Next);
+                    },
+                };
+                self.looping(|this| {
+                    this.status = 0;
+                    for word in words {
+                        if let Err(e) = this.set_var(name, word) {
+                            this.status = 1;
+                            screen.error("ysh", e)?;
+                            return Ok(Flow::Next);
+                        }
+                        if let Some(flow) = lap(this.exec(body, screen)?) {
+                            return Ok(flow);
+                        }
+                    }
+                    Ok(Flow::Next)
+                })
+            },
+            Command::Case { word, arms } => {
+                let found = expand::word(word, self)
+                    .and_then(|word| self.case_arm(&word, arms));
+                match found {
+                    Ok(Some(body)) => self.exec(body, screen),
+                    Ok(None) => {
+                        self.status = 0;
+                        Ok(Flow::Next)
+                    },
+                    Err(e) => {
+                        self.status = 1;
+                        screen.error("ysh", e)?;
+                        Ok(Flow::Next)
+                    },
+                }
+            },
+            Command::Subshell { body, redirects } => {
+                let mut copy = self.clone();
+                //  The working directory belongs to the process, which the copy
+                //  shares, so it is put back after.
+                let dir = env::current_dir();
+                let flow = copy.redirected(redirects, screen, |this, screen| this.exec(body, screen));
+                if let Ok(dir) = dir {
+                    env::set_current_dir(dir)?;
+                }
+                self.status = copy.status;
+                //  Leaving the subshell is all that `exit` or `break` can do.
+                match flow? {
+                    Flow::Interrupt => Ok(Flow::Interrupt),
+                    _ => Ok(Flow::Next),
+                }
+            },
+            Command::Group { body, redirects } => {
+                self.redirected(redirects, screen, |this, screen| this.exec(body, screen))
+            },
+            Command::Function { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                self.status = 0;
+                Ok(Flow::Next)
+            },
+        }
+    }
+
+    /// Runs the commands in a file in the shell itself, as for `source` and the
+    /// startup files. `return` leaves the file.
+    pub fn source(&mut self, path: &Path, screen: &mut Screen) -> Result<Flow, Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.status = 1;
+                screen.error("source", format!("{}: {}", path.display(), e))?;
+                return Ok(Flow::Next);
+            },
+        };
+        let script = match Script::parse_from(&text) {
+            Ok(script) => script,
+            Err(ParseError::NoInput) => {
+                self.status = 0;
+                return Ok(Flow::Next);
+            },
+            Err(e) => {
+                self.status = 2;
+                screen.error(path.display(), e)?;
+                return Ok(Flow::Next);
+            },
+        };
+        self.sourcing += 1;
+        let flow = self.exec(&script.commands, screen);
+        self.sourcing -= 1;
+        match flow? {
+            Flow::Return => Ok(Flow::Next),
+            flow => Ok(flow),
+        }
+    }
+
+    /// Runs a subshell or a group, with the input given by its redirections.
+    fn redirected(
+        &mut self,
+        redirects: &str,
+        screen: &mut Screen,
+        run: impl FnOnce(&mut Self, &mut Screen) -> Result<Flow, Error>,
+    ) -> Result<Flow, Error> {
+        if redirects.is_empty() {
+            return run(self, screen);
+        }
+        //  When there are several inputs, the last one wins.
+        let input = match here::split(redirects) {
+            Ok((_, mut found)) => match found.pop().map(|h| expand::here(&h, self)) {
+                Some(Ok(text)) => text,
+                Some(Err(e)) => {
+                    self.status = 1;
+                    screen.error("ysh", e)?;
+                    return Ok(Flow::Next);
+                },
+                None => return run(self, screen),
+            },
+            Err(e) => {
+                self.status = 2;
+                screen.error("ysh", e)?;
+                return Ok(Flow::Next);
+            },
+        };
+        let input = match here_file(&input) {
+            Ok(file) => file,
+            Err(e) => {
+                self.status = 1;
+                screen.error("ysh", e)?;
+                return Ok(Flow::Next);
+            },
+        };
+        let outer = mem::replace(&mut self.input, Some(Rc::new(input)));
+        let flow = run(self, screen);
+        self.input = outer;
+        flow
+    }
+
+    /// Runs a function with its arguments as the positional parameters, and
+    /// with a new scope for `local` variables.
+    fn call(
+        &mut self,
+        name: &str,
+        body: Rc<Vec<Command>>,
+        args: Vec<String>,
+        screen: &mut Screen,
+    ) -> Result<Flow, Error> {
+        if self.locals.len() >= MAX_CALLS {
+            self.status = 1;
+            screen.error(name, "maximum function nesting level exceeded")?;
+            return Ok(Flow::Next);
+        }
+        let args = mem::replace(&mut self.args, args);
+        self.locals.push(BTreeMap::new());
+        let flow = self.exec(&body, screen);
+        self.locals.pop();
+        self.args = args;
+        match flow? {
+            Flow::Return => Ok(Flow::Next),
+            flow => Ok(flow),
+        }
+    }
+
+    /// Runs a loop, counting it as one of the loops that `break` and
+    /// `continue` may leave.
+    fn looping(&mut self, run: impl FnOnce(&mut Self) -> Result<Flow, Error>) -> Result<Flow, Error> {
+        self.loops += 1;
+        let flow = run(self);
+        self.loops -= 1;
+        flow
+    }
+
+    /// Finds the body of the first `case` arm with a pattern matching `word`.
+    fn case_arm<'c>(
+        &mut self,
+        word: &str,
+        arms: &'c [CaseArm],
+    ) -> Result<Option<&'c [Command]>, expand::ExpandError> {
+        for arm in arms {
+            for pattern in &arm.patterns {
+                if glob::matches(&expand::pattern(pattern, self)?, word) {
+                    return Ok(Some(&arm.body));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn exec_simple(&mut self, text: &str, screen: &mut Screen) -> Result<Flow, Error> {
+        if let Some((text, names)) = self.alias(text) {
+            //  An alias may stand for several commands, or a compound one.
+            let script = match Script::parse_from(&text) {
+                Ok(script) => script,
+                Err(ParseError::NoInput) => return Ok(Flow::Next),
+                Err(e) => {
+                    self.status = 2;
+                    screen.error("ysh", e)?;
+                    return Ok(Flow::Next);
+                },
+            };
+            let depth = self.aliasing.len();
+            self.aliasing.extend(names);
+            let flow = self.exec(&script.commands, screen);
+            self.aliasing.truncate(depth);
+            return flow;
+        }
+        //  The command is on the first line, and the rest of the text holds its
+        //  here-documents.
+        //  When a command has several inputs, the last one wins.
+        let (line, mut input) = match here::split(text) {
+            Ok(split) => split,
+            Err(e) => {
+                self.status = 2;
+                screen.error("ysh", e)?;
+                return Ok(Flow::Next);
+            },
+        };
+        if let Ok((rest, expr)) = token::arith(line.trim_start()) {
+            if rest.trim().is_empty() {
+                match expand::evaluate(expr, self) {
+                    Ok(value) => self.status = if value != 0 { 0 } else { 1 },
+                    Err(e) => {
+                        self.status = 1;
+                        screen.error("ysh", e)?;
+                    },
+                }
+                return Ok(Flow::Next);
+            }
+        }
+        let (line, found) = match process::substitute(line, self) {
+            Ok(substituted) => substituted,
+            Err(e) => {
+                self.status = 1;
+                screen.error("ysh", e)?;
+                return Ok(Flow::Next);
+            },
+        };
+        let flow = self.exec_words(&line, input.pop(), screen);
+        //  The substituted commands run alongside the command, and are done
+        //  once it is.
+        let printed = process::finish(found)?;
+        if !printed.is_empty() {
+            screen.command_output(&printed)?;
+        }
+        flow
+    }
+
+    /// Expands the words of a simple command and runs it.
+    ///
+    /// Assignments in front of the command set variables for the command
+    /// alone, or for the shell if there is no command.
+    fn exec_words(&mut self, line: &str, input: Option<Here>, screen: &mut Screen) -> Result<Flow, Error> {
+        let (assignments, line) = assignments(line);
+        //  Every word is expanded before the command is looked up, so builtins
+        //  see their arguments as programs do.
+        let words = match self.expand_words(line) {
+            Ok(words) => words,
+            Err(e) => {
+                self.status = 1;
+                screen.error("ysh", e)?;
+                return Ok(Flow::Next);
+            },
+        };
+        if words.is_empty() && !assignments.is_empty() {
+            //  Each assignment sees the ones before it.
+            self.status = 0;
+            for assignment in assignments {
+                if let Err(e) = self.assign(&assignment, false) {
+                    self.status = 1;
+                    screen.error("ysh", e)?;
+                    return Ok(Flow::Next);
+                }
+            }
+            return Ok(Flow::Next);
+        }
+        let mut vars = Vec::new();
+        for assignment in assignments {
+            match self.assigned_value(&assignment) {
+                Ok(value) => vars.push((assignment.name, value)),
+                Err(e) => {
+                    self.status = 1;
+                    screen.error("ysh", e)?;
+                    return Ok(Flow::Next);
+                },
+            }
+        }
+        if vars.is_empty() {
+            return self.exec_expanded(words, input, screen);
+        }
+        self.with_vars(vars, screen, |this, screen| this.exec_expanded(words, input, screen))
+    }
+
+    /// Expands the words of a command line.
+    ///
+    /// The assignments given to `declare` and its forms are left as they are,
+    /// to be expanded as assignments, rather than as words.
+    fn expand_words(&mut self, line: &str) -> Result<Vec<String>, expand::ExpandError> {
+        let mut raw = Words::new(line);
+        let first = match raw.next() {
+            Some(first) => first,
+            None => return Ok(Vec::new()),
+        };
+        let declaring = DECLARATIONS.contains(&first);
+        let mut words = expand::words(Some(first), self)?;
+        for word in raw {
+            if declaring && Assignment::parse(word).is_some() {
+                words.push(word.to_string());
+            } else {
+                words.extend(expand::words(Some(word), self)?);
+            }
+        }
+        Ok(words)
+    }
+
+    /// Performs an assignment to a variable, or to an element of an array.
+    ///
+    /// The variable is the innermost one of its name, or the global one when
+    /// `global` is set, as for `declare -g`.
+    fn assign(&mut self, assignment: &Assignment, global: bool) -> Result<(), Error> {
+        let Assignment { name, subscript, append, value } = *assignment;
+        let current = |this: &Self| if global {
+            this.vars.get(name).and_then(|var| var.value.clone())
+        } else {
+            this.value(name).cloned()
+        };
+        let list = match token::list(value) {
+            Ok(("", list)) => list,
+            _ => {
+                let value = expand::word(value, self)?;
+                match subscript {
+                    None => {
+                        let old = match current(self) {
+                            Some(ref old) if append => old.scalar().unwrap_or_default().to_string(),
+                            _ => String::new(),
+                        };
+                        match self.value_mut_in(name, global)? {
+                            Some(array) => array.set_element(Subscript::Index(0), old + &value),
+                            slot => *slot = Some(Value::Scalar(old + &value)),
+                        }
+                    },
+                    Some(sub) => {
+                        let sub = expand::subscript(name, sub, self)?;
+                        let slot = self.value_mut_in(name, global)?;
+                        let old = match slot {
+                            Some(ref old) if append => old.element(&sub).unwrap_or_default().to_string(),
+                            _ => String::new(),
+                        };
+                        slot.get_or_insert_with(|| Value::Indexed(BTreeMap::new()))
+                            .set_element(sub, old + &value);
+                    },
+                }
+                return Ok(());
+            },
+        };
+        if subscript.is_some() {
+            bail!("{}[{}]: cannot assign a list to an array element", name, subscript.unwrap_or_default());
+        }
+        let current = current(self);
+        let assoc = match current {
+            Some(Value::Assoc(_)) => true,
+            _ => false,
+        };
+        let mut array = match current {
+            Some(old) if append => old,
+            _ if assoc => Value::Assoc(BTreeMap::new()),
+            _ => Value::Indexed(BTreeMap::new()),
+        };
+        //  Elements without a subscript follow the one before.
+        let mut next = array.end();
+        for word in Words::new(list) {
+            if let Some((key, value)) = keyed(word) {
+                let sub = if assoc {
+                    Subscript::Key(expand::word(key, self)?)
+                } else {
+                    let n = expand::evaluate(key, self)?;
+                    if n < 0 {
+                        bail!("{}[{}]: bad array subscript", name, key);
+                    }
+                    next = n as usize + 1;
+                    Subscript::Index(n as usize)
+                };
+                let value = expand::word(value, self)?;
+                array.set_element(sub, value);
+                continue;
+            }
+            if assoc {
+                bail!("{}: {}: must use subscript when assigning associative array", name, word);
+            }
+            for value in expand::words(Some(word), self)? {
+                array.set_element(Subscript::Index(next), value);
+                next += 1;
+            }
+        }
+        *self.value_mut_in(name, global)? = Some(array);
+        Ok(())
+    }
+
+    /// Removes one element of an array.
+    fn unset_element(&mut self, name: &str, subscript: &str) -> Result<(), Error> {
+        let sub = expand::subscript(name, subscript, self)?;
+        if let Some(value) = self.value_mut(name)? {
+            value.remove(&sub);
+        }
+        Ok(())
+    }
+
+    /// Expands the value of an assignment in front of a command, which can only
+    /// set a string.
+    fn assigned_value(&mut self, assignment: &Assignment) -> Result<String, Error> {
+        if assignment.subscript.is_some() || token::list(assignment.value).is_ok() {
+            bail!("{}: arrays cannot be assigned for a command", assignment.name);
+        }
+        let value = expand::word(assignment.value, self)?;
+        match self.var(assignment.name) {
+            Some(old) if assignment.append => Ok(old + &value),
+            _ => Ok(value),
+        }
+    }
+
+    /// Runs a simple command whose words have been expanded.
+    fn exec_expanded(&mut self, words: Vec<String>, input: Option<Here>, screen: &mut Screen) -> Result<Flow, Error> {
+        match Builtin::from_args(words.iter().map(String::as_str)) {
+            Err(ParseError::NoInput) => Ok(Flow::Next),
+            Err(ParseError::Other(e)) => {
+                self.status = 2;
+                screen.error("ysh", e)?;
+                Ok(Flow::Next)
+            },
+            Ok(builtin) => self.exec_builtin(builtin, screen),
+            Err(ParseError::Unrecognized) => {
+                if let Some(body) = self.functions.get(&words[0]).cloned() {
+                    return self.call(&words[0], body, words[1 ..].to_vec(), screen);
+                }
+                self.invoke(&words[0], &words[1 ..], input, screen)
+            },
+        }
+    }
+
+    /// Runs a command with variables that are set and exported for it alone,
+    /// and put back as they were after.
+    fn with_vars(
+        &mut self,
+        vars: Vec<(&str, String)>,
+        screen: &mut Screen,
+        run: impl FnOnce(&mut Self, &mut Screen) -> Result<Flow, Error>,
+    ) -> Result<Flow, Error> {
+        if let Some((name, _)) = vars.iter().find(|(name, _)| {
+            self.lookup(name).map_or(false, |var| var.readonly)
+        }) {
+            self.status = 1;
+            screen.error("ysh", st::ReadOnly(name.to_string()))?;
+            return Ok(Flow::Next);
+        }
+        let mut saved = Vec::new();
+        for (name, value) in vars {
+            let scope = self.scope_mut(name);
+            let var = Var { value: Some(Value::Scalar(value)), exported: true, readonly: false };
+            saved.push((name, scope.insert(name.to_string(), var)));
+        }
+        let flow = run(self, screen);
+        for (name, old) in saved.into_iter().rev() {
+            let scope = self.scope_mut(name);
+            match old {
+                Some(var) => scope.insert(name.to_string(), var),
+                None => scope.remove(name),
+            };
+        }
+        flow
+    }
+
+    /// Replaces an alias at the start of a simple command with its value.
+    ///
+    /// When the value ends with a blank, the word after the alias is checked
+    /// for an alias too. Returns the new text and the names of the aliases
+    /// used, or `None` if the command does not begin with an alias. An alias
+    /// that is already being expanded is not expanded again, which ends loops
+    /// like `alias ls='ls -F'`.
+    fn alias(&self, text: &str) -> Option<(String, Vec<String>)> {
+        //  Only the command's line is searched, not its here-documents.
+        let end = text.find('\n').unwrap_or(text.len());
+        let mut rest = &text[.. end];
+        let mut out = String::new();
+        let mut names: Vec<String> = Vec::new();
+        while let Ok((after, word)) = token::trim_left(token::shell_word)(rest) {
+            let seen = self.aliasing.iter().chain(&names).any(|name| name == word);
+            let value = match self.aliases.get(word) {
+                Some(value) if !seen => value,
+                _ => break,
+            };
+            out.push_str(value);
+            names.push(word.to_string());
+            rest = after;
+            if !value.ends_with(|c| c == ' ' || c == '\t') {
+                break;
+            }
+        }
+        if names.is_empty() {
+            return None;
+        }
+        out.push_str(rest);
+        out.push_str(&text[end ..]);
+        Some((out, names))
+    }
+
+    fn exec_builtin(&mut self, builtin: Builtin, screen: &mut Screen) -> Result<Flow, Error> {
+        match builtin {
+            Builtin::Alias(args) => {
+                self.status = 0;
+                if args.is_empty() {
+                    for (name, value) in &self.aliases {
+                        write!(screen, "alias {}={}", name, quote(value))?;
+                        screen.newline()?;
+                    }
+                }
+                for arg in args {
+                    if let Some(n) = arg.find('=') {
+                        self.aliases.insert(arg[.. n].to_string(), arg[n + 1 ..].to_string());
+                        continue;
+                    }
+                    match self.aliases.get(arg) {
+                        Some(value) => {
+                            write!(screen, "alias {}={}", arg, quote(value))?;
+                            screen.newline()?;
+                        },
+                        None => {
+                            self.status = 1;
+                            screen.error("alias", format!("{}: not found", arg))?;
+                        },
+                    }
+                }
+            },
+            Builtin::Break(n) => {
+                return self.leave("break", Flow::Break(n), screen);
+            },
+            Builtin::Continue(n) => {
+                return self.leave("continue", Flow::Continue(n), screen);
+            },
+            Builtin::Clear => {
+                self.status = 0;
+                screen.clear()?;
+            },
+            Builtin::Cd(args) => {
+                let moved = self.change_dir(args.to, args.physical);
+                self.status = if moved.is_ok() { 0 } else { 1 };
+                match moved {
+                    Ok(Some(dir)) => {
+                        write!(screen, "{}", dir.display())?;
+                        screen.newline()?;
+                    },
+                    Ok(None) => {},
+                    Err(e) => screen.error("cd", e)?,
+                }
+            },
+            Builtin::Exit(status) => {
+                //  Like a return status, the status is only the low eight bits.
+                if let Some(status) = status {
+                    self.status = status & 0xff;
+                }
+                return Ok(Flow::Exit);
+            },
+            Builtin::Declare(declare) => {
+                self.declare(declare, screen)?;
+            },
+            Builtin::Dirs(opts) => {
+                self.status = 0;
+                if opts.clear {
+                    self.dirs.clear();
+                    self.save_dirs();
+                } else {
+                    self.print_dirs(screen, &opts)?;
+                }
+            },
+            Builtin::Pushd(pushd) => {
+                let pushed = match pushd {
+                    Pushd::Swap => self.swap_dirs(),
+                    Pushd::Rotate(index) => self.rotate_dirs(index),
+                    Pushd::Dir(to) => self.push_dir(to),
+                };
+                self.status = if pushed.is_ok() { 0 } else { 1 };
+                match pushed {
+                    Ok(()) => self.print_dirs(screen, &Dirs::default())?,
+                    Err(e) => screen.error("pushd", e)?,
+                }
+            },
+            Builtin::Popd(index) => {
+                let popped = self.pop_dir(index);
+                self.status = if popped.is_ok() { 0 } else { 1 };
+                match popped {
+                    Ok(()) => self.print_dirs(screen, &Dirs::default())?,
+                    Err(e) => screen.error("popd", e)?,
+                }
+            },
+            Builtin::Return(status) => {
+                if self.locals.is_empty() && self.sourcing == 0 {
+                    self.status = 1;
+                    screen.error("return", "can only `return` from a function or sourced script")?;
+                    return Ok(Flow::Next);
+                }
+                //  Like an exit status, the status is only the low eight bits.
+                if let Some(status) = status {
+                    self.status = status & 0xff;
+                }
+                return Ok(Flow::Return);
+            },
+            Builtin::Set(Some(args)) => {
+                self.status = 0;
+                self.args = args.into_iter().map(str::to_string).collect();
+            },
+            Builtin::Set(None) => {
+                self.status = 0;
+                for (name, var) in self.visible_vars() {
+                    if let Some(ref value) = var.value {
+                        write!(screen, "{}={}", name, quote_value(value))?;
+                        screen.newline()?;
+                    }
+                }
+            },
+            Builtin::Shift(count) => {
+                if count > self.args.len() {
+                    self.status = 1;
+                    screen.error("shift", format!("{}: shift count out of range", count))?;
+                    return Ok(Flow::Next);
+                }
+                self.status = 0;
+                self.args.drain(.. count);
+            },
+            Builtin::Shopt(shopt) => {
+                self.status = 0;
+                for name in &shopt.names {
+                    match (self.options.get_mut(name), shopt.set) {
+                        (Some(opt), Some(value)) => *opt = value,
+                        (Some(_), None) => {},
+                        (None, _) => {
+                            self.status = 1;
+                            screen.error("shopt", format!("{}: invalid option name", name))?;
+                        },
+                    }
+                }
+                //  Without `-s` or `-u`, report the options.
+                if shopt.set.is_none() {
+                    for (name, value) in self.options.list() {
+                        if shopt.names.is_empty() || shopt.names.contains(&name) {
+                            write!(screen, "{:<15} {}", name, if value { "on" } else { "off" })?;
+                            screen.newline()?;
+                        }
+                    }
+                }
+            },
+            Builtin::Source(file, args) => {
+                let path = match find_source(file, &self.var("PATH").unwrap_or_default()) {
+                    Some(path) => path,
+                    None => {
+                        self.status = 1;
+                        screen.error("source", format!("{}: file not found", file))?;
+                        return Ok(Flow::Next);
+                    },
+                };
+                //  Arguments replace the positional parameters while the file
+                //  runs. Without any, the file sees those of the shell.
+                if args.is_empty() {
+                    return self.source(&path, screen);
+                }
+                let args = args.into_iter().map(str::to_string).collect();
+                let args = mem::replace(&mut self.args, args);
+                let flow = self.source(&path, screen);
+                self.args = args;
+                return flow;
+            },
+            Builtin::Unalias(unalias) => {
+                self.status = 0;
+                if unalias.all {
+                    self.aliases.clear();
+                }
+                for name in unalias.names {
+                    if self.aliases.remove(name).is_none() && !unalias.all {
+                        self.status = 1;
+                        screen.error("unalias", format!("{}: not found", name))?;
+                    }
+                }
+            },
+            Builtin::Unset(unset) => {
+                self.status = 0;
+                for name in unset.names {
+                    if unset.functions {
+                        self.functions.remove(name);
+                        continue;
+                    }
+                    let unset = match Assignment::parse(&format!("{}=", name)) {
+                        Some(Assignment { name, subscript: Some(sub), .. }) => {
+                            self.unset_element(name, sub)
+                        },
+                        _ if is_name(name) => self.unset_var(name).map_err(Error::from),
+                        _ => Err(err_msg(format!("`{}`: not a valid identifier", name))),
+                    };
+                    if let Err(e) = unset {
+                        self.status = 1;
+                        screen.error("unset", e)?;
+                    }
+                }
+            },
+            Builtin::Z(z) => {
+                self.status = 0;
+                if z.list || z.fragments.is_empty() {
+                    for dir in self.frecency.matches(&z.fragments).into_iter().rev() {
+                        write!(screen, "{}", dir.display())?;
+                        screen.newline()?;
+                    }
+                } else {
+                    let best = self.frecency.matches(&z.fragments)
+                        .first()
+                        .map(|dir| dir.to_path_buf());
+                    let moved = match best {
+                        Some(dir) => self.cd(dir).map_err(Error::from),
+                        None => Err(err_msg("no match found")),
+                    };
+                    if let Err(e) = moved {
+                        self.status = 1;
+                        screen.error("z", e)?;
+                    }
+                }
+            },
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Runs `declare` or one of its forms, which set variables and their
+    /// attributes.
+    fn declare(&mut self, declare: Declare, screen: &mut Screen) -> Result<(), Error> {
+        self.status = 0;
+        let local = !declare.global && !self.locals.is_empty();
+        if declare.builtin == "local" && !local {
+            self.status = 1;
+            screen.error("local", "can only be used in a function")?;
+            return Ok(());
+        }
+        if declare.print {
+            return self.print_vars(&declare, screen);
+        }
+        for arg in declare.vars {
+            let assignment = Assignment::parse(arg);
+            let name = assignment.map_or(arg, |a| a.name);
+            if !is_name(name) {
+                self.status = 1;
+                screen.error(declare.builtin, format!("`{}`: not a valid identifier", arg))?;
+                continue;
+            }
+            //  Declaring a variable again keeps its value.
+            let var = self.declared(name, local);
+            if var.readonly && (assignment.is_some() || declare.export == Some(false)) {
+                self.status = 1;
+                screen.error(declare.builtin, st::ReadOnly(name.to_string()))?;
+                continue;
+            }
+            //  A string becomes the first element of an array.
+            if let (Some(Value::Indexed(_)), true) = (&var.value, declare.assoc) {
+                self.status = 1;
+                screen.error(declare.builtin, format!("{}: cannot convert indexed to associative array", name))?;
+                continue;
+            }
+            let converted = match (var.value.take(), declare.indexed, declare.assoc) {
+                (None, _, true) => Some(Value::Assoc(BTreeMap::new())),
+                (None, true, _) => Some(Value::Indexed(BTreeMap::new())),
+                (Some(Value::Scalar(value)), true, false) => {
+                    Some(Value::Indexed(Some((0, value)).into_iter().collect()))
+                },
+                (Some(Value::Scalar(value)), _, true) => {
+                    Some(Value::Assoc(Some(("0".to_string(), value)).into_iter().collect()))
+                },
+                (value, ..) => value,
+            };
+            var.value = converted;
+            if let Some(export) = declare.export {
+                var.exported = export;
+            }
+            if let Some(assignment) = assignment {
+                //  A local variable is the innermost one of its name, but a
+                //  global one may be hidden by a local one.
+                if let Err(e) = self.assign(&assignment, !local) {
+                    self.status = 1;
+                    screen.error(declare.builtin, e)?;
+                    continue;
+                }
+            }
+            self.declared(name, local).readonly |= declare.readonly;
+        }
+        Ok(())
+    }
+
+    /// Gets a variable given to `declare`, creating it if need be: in the
+    /// scope of the running function if it is `local`, or else among the
+    /// global variables.
+    fn declared(&mut self, name: &str, local: bool) -> &mut Var {
+        let scope = if local {
+            self.locals.last_mut().expect("a function is running")
+        } else {
+            &mut self.vars
+        };
+        scope.entry(name.to_string()).or_default()
+    }
+
+    /// Prints variables as the `declare` commands that would recreate them:
+    /// those named, or else all that have the attributes asked for.
+    fn print_vars(&mut self, declare: &Declare, screen: &mut Screen) -> Result<(), Error> {
+        let vars = self.visible_vars();
+        let shown: Vec<(&str, &Var)> = if declare.vars.is_empty() {
+            vars.iter()
+                .map(|(name, var)| (*name, *var))
+                .filter(|(_, var)| {
+                    declare.export.map_or(true, |export| var.exported == export)
+                        && (!declare.readonly || var.readonly)
+                })
+                .collect()
+        } else {
+            declare.vars.iter()
+                .filter_map(|name| vars.get(name).map(|var| (*name, *var)))
+                .collect()
+        };
+        let mut status = 0;
+        for name in &declare.vars {
+            if !vars.contains_key(name) {
+                status = 1;
+                screen.error(declare.builtin, format!("{}: not found", name))?;
+            }
+        }
+        for (name, var) in shown {
+            let mut flags = String::from("-");
+            match var.value {
+                Some(Value::Indexed(_)) => flags.push('a'),
+                Some(Value::Assoc(_)) => flags.push('A'),
+                _ => {},
+            }
+            if var.readonly {
+                flags.push('r');
+            }
+            if var.exported {
+                flags.push('x');
+            }
+            if flags.len() == 1 {
+                flags.push('-');
+            }
+            match var.value {
+                Some(ref value) => write!(screen, "declare {} {}={}", flags, name, quote_value(value))?,
+                None => write!(screen, "declare {} {}", flags, name)?,
+            }
+            screen.newline()?;
+        }
+        self.status = status;
+        Ok(())
+    }
+
+    /// Gets every variable that can be seen from where the shell is running,
+    /// by name.
+    fn visible_vars(&self) -> BTreeMap<&str, &Var> {
+        Some(&self.vars).into_iter()
+            .chain(&self.locals)
+            .flat_map(|scope| scope.iter())
+            .map(|(name, var)| (name.as_str(), var))
+            .collect()
+    }
+
+    /// Runs a program, found on the `PATH`, and prints its output.
+    fn invoke(
+        &mut self,
+        command: &str,
+        args: &[String],
+        input: Option<Here>,
+        screen: &mut Screen,
+    ) -> Result<Flow, Error> {
+        let input = match input.as_ref().map(|h| expand::here(h, self)) {
+            None => None,
+            Some(Ok(text)) => Some(text),
+            Some(Err(e)) => {
+                self.status = 1;
+                screen.error("ysh", e)?;
+                return Ok(Flow::Next);
+            },
+        };
+        let mut job = cmd(command, args)
+            .full_env(self.environment())
+            .unchecked()
+            .stdout_capture()
+            .stderr_capture();
+        if let Some(input) = input {
+            job = job.input(input);
+        } else if let Some(ref file) = self.input {
+            match file.try_clone() {
+                Ok(file) => job = job.stdin_file(file),
+                Err(e) => {
+                    self.status = 1;
+                    screen.error("ysh", e)?;
+                    return Ok(Flow::Next);
+                },
+            }
+        }
+        //  The terminal is out of raw mode while commands run (see `cooked`),
+        //  so Ctrl-C is a SIGINT for the job.
+        let exec = job.run();
+        self.status = match exec {
+            Ok(ref exec) => st::exit_code(exec.status),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 127,
+            Err(_) => 126,
+        };
+        let interrupted = exec.as_ref().map_or(false, |exec| st::interrupted(exec.status));
+        exec
+            .map_err(Into::into)
+            .and_then(|exec| {
+                if &exec.stdout != b"" {
+                    screen.command_output(&exec.stdout)?;
+                } else if &exec.stderr != b"" {
+                    screen.command_output(&exec.stderr)?;
+                }
+                Ok(())
+            })
+            .or_else(|err: Error| {
+                if err.find_root_cause()
+                    .downcast_ref::<io::Error>()
+                    .iter()
+                    .any(|e| e.kind() == io::ErrorKind::NotFound)
+                {
+                    screen.not_found(command)
+                } else {
+                    screen.error("ysh", err)
+                }
+            })?;
+        if interrupted {
+            return Ok(Flow::Interrupt);
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Runs `break` or `continue`, which are only meaningful inside a loop.
+    fn leave(&mut self, name: &str, flow: Flow, screen: &mut Screen) -> Result<Flow, Error> {
+        self.status = 0;
+        if self.loops == 0 {
+            screen.error(name, "only meaningful in a `for`, `while`, or `until` loop")?;
+            return Ok(Flow::Next);
+        }
+        Ok(flow)
+    }
+}
+
+/// Finds the file named by `source`. A name without a `/` is searched for in
+/// each entry of `path`, and then in the working directory.
+fn find_source(file: &str, path: &str) -> Option<PathBuf> {
+    if !file.contains('/') {
+        let found = env::split_paths(path)
+            .map(|dir| dir.join(file))
+            .find(|path| path.is_file());
+        if found.is_some() {
+            return found;
+        }
+    }
+    Some(PathBuf::from(file)).filter(|path| path.is_file())
+}
+
+/// An assignment: `name=value`, or `name+=value` to append to the value,
+/// either of which may set an element of an array, as `name[subscript]=value`
+/// does. The value is a raw word, or a parenthesized list of them for an array.
+#[derive(Clone, Copy, Debug)]
+struct Assignment<'a> {
+    name: &'a str,
+    subscript: Option<&'a str>,
+    append: bool,
+    value: &'a str,
+}
+
+impl<'a> Assignment<'a> {
+    /// Recognizes an assignment in a raw word.
+    fn parse(word: &'a str) -> Option<Self> {
+        let end = word.find(|c: char| !(c == '_' || c.is_ascii_alphanumeric()))
+            .unwrap_or(word.len());
+        let name = &word[.. end];
+        if !is_name(name) {
+            return None;
+        }
+        let mut rest = &word[end ..];
+        let mut subscript = None;
+        if rest.starts_with('[') {
+            let close = rest.find(']')?;
+            subscript = Some(&rest[1 .. close]);
+            rest = &rest[close + 1 ..];
+        }
+        let append = rest.starts_with("+=");
+        let value = match rest.find('=') {
+            Some(1) if append => &rest[2 ..],
+            Some(0) => &rest[1 ..],
+            _ => return None,
+        };
+        Some(Assignment { name, subscript, append, value })
+    }
+}
+
+/// Writes the text of a here-document to a file, and opens it for reading from
+/// the start.
+///
+/// The file has no name by the time it is returned, so it goes away once it is
+/// closed.
+fn here_file(text: &str) -> io::Result<File> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("ysh-here-{}-{}", std::process::id(), count));
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        //  Windows cannot remove a file that is open.
+        options.custom_flags(winapi::um::winbase::FILE_FLAG_DELETE_ON_CLOSE);
+    }
+    let mut file = options.open(&path)?;
+    #[cfg(unix)]
+    fs::remove_file(&path)?;
+    file.write_all(text.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Splits the assignments off the front of a command line.
+fn assignments(line: &str) -> (Vec<Assignment>, &str) {
+    let mut found = Vec::new();
+    let mut rest = line;
+    while let Ok((after, word)) = token::trim_left(token::shell_word)(rest) {
+        match Assignment::parse(word) {
+            Some(assignment) => found.push(assignment),
+            None => break,
+        }
+        rest = after;
+    }
+    (found, rest)
+}
+
+/// Splits an element of a list assigned to an array, `[subscript]=value`, into
+/// the subscript and the value.
+fn keyed(word: &str) -> Option<(&str, &str)> {
+    if !word.starts_with('[') {
+        return None;
+    }
+    let close = word.find("]=")?;
+    Some((&word[1 .. close], &word[close + 2 ..]))
+}
+
+/// Quotes text in single quotes, so that the shell reads it back unchanged.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Quotes the value of a variable so that the shell reads it back unchanged:
+/// arrays as lists of their elements with subscripts.
+fn quote_value(value: &Value) -> String {
+    let elements: Vec<String> = match value {
+        Value::Scalar(value) => return quote(value),
+        Value::Indexed(elements) => elements.iter()
+            .map(|(n, value)| format!("[{}]={}", n, quote(value)))
+            .collect(),
+        Value::Assoc(elements) => elements.iter()
+            .map(|(key, value)| format!("[{}]={}", quote(key), quote(value)))
+            .collect(),
+    };
+    format!("({})", elements.join(" "))
+}
+
+/// Decides whether a loop goes on after a run of its test or body ended with
+/// `flow`. If it does not, this is what the loop as a whole ends with.
+fn lap(flow: Flow) -> Option<Flow> {
+    match flow {
+        Flow::Next | Flow::Continue(1) => None,
+        Flow::Break(1) => Some(Flow::Next),
+        Flow::Break(n) => Some(Flow::Break(n - 1)),
+        Flow::Continue(n) => Some(Flow::Continue(n - 1)),
+        other => Some(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a script, and gives its status.
+    fn run(state: &mut State, text: &str) -> i32 {
+        let mut screen = Screen::new(false);
+        state.script(text, &mut screen).expect("the script runs");
+        state.status
+    }
+
+    #[test]
+    fn aliases() {
+        let mut state = State::default();
+        for (name, value) in &[("ll", "ls -l"), ("ls", "ls -F"), ("sudo", "sudo "), ("a", "b"), ("b", "a")] {
+            state.aliases.insert(name.to_string(), value.to_string());
+        }
+        let alias = |state: &State, text| state.alias(text).map(|(text, _)| text);
+        assert_eq!(alias(&state, "  ll src"), Some("ls -l src".into()));
+        assert_eq!(alias(&state, "sudo ll"), Some("sudo ls -l".into()));
+        assert_eq!(alias(&state, "cat <<EOF\nll\nEOF"), None);
+        assert_eq!(alias(&state, "'ll' src"), None);
+        assert_eq!(alias(&state, "echo ll"), None);
+
+        //  The expansion of `ll` expands `ls` once more, but not again.
+        state.aliasing.push("ll".into());
+        assert_eq!(alias(&state, "ls -l src"), Some("ls -F -l src".into()));
+        state.aliasing.push("ls".into());
+        assert_eq!(alias(&state, "ls -F -l src"), None);
+
+        state.aliasing.clear();
+        assert_eq!(state.alias("a").map(|(_, names)| names), Some(vec!["a".to_string()]));
+        state.aliasing.push("a".into());
+        assert_eq!(alias(&state, "b"), Some("a".into()));
+        state.aliasing.push("b".into());
+        assert_eq!(alias(&state, "a"), None);
+
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn source() {
+        let path = env::temp_dir().join(format!("ysh-source-{}", std::process::id()));
+        fs::write(&path, "alias ll='ls -l'\nif (( $# == 2 )); then return 3; fi\nalias la='ls -a'\n")
+            .expect("the script is written");
+        let mut screen = Screen::new(false);
+        let mut state = State::default();
+
+        let flow = state.source(&path, &mut screen).expect("the script runs");
+        assert_eq!(flow, Flow::Next);
+        assert_eq!(state.status, 0);
+        assert_eq!(state.sourcing, 0);
+        assert!(state.aliases.contains_key("ll"));
+        assert!(state.aliases.contains_key("la"));
+
+        //  `return` leaves the file early, with its status.
+        state.aliases.clear();
+        state.args = vec!["a".into(), "b".into()];
+        state.source(&path, &mut screen).expect("the script runs");
+        assert_eq!(state.status, 3);
+        assert!(state.aliases.contains_key("ll"));
+        assert!(!state.aliases.contains_key("la"));
+
+        fs::remove_file(&path).expect("the script is removed");
+        state.source(&path, &mut screen).expect("the error is reported");
+        assert_eq!(state.status, 1);
+    }
+
+    #[test]
+    fn here_docs() {
+        let path = env::temp_dir().join(format!("ysh-here-docs-{}", std::process::id()));
+        let mut state = State::default();
+        //  Runs a script with `OUT` in place of the path, and gives what was
+        //  written there.
+        let mut input = |script: &str| {
+            assert_eq!(run(&mut state, &script.replace("OUT", &path.display().to_string())), 0);
+            let text = fs::read_to_string(&path).expect("the input is written");
+            fs::remove_file(&path).expect("the file is removed");
+            text
+        };
+        let cat = "sh -c 'cat > \"$0\"' OUT";
+
+        let text = input(&format!("x=1; {} <<EOF -n\nhello $((x))\n\tworld\nEOF\n", cat));
+        assert_eq!(text, "hello 1\n\tworld\n");
+        let text = input(&format!("{} <<-'EOF'\n\thello $x\n\tEOF", cat));
+        assert_eq!(text, "hello $x\n");
+        //  The bodies come in the order of their operators, and the last wins.
+        assert_eq!(input(&format!("{} <<A <<B\na\nA\nb\nB", cat)), "b\n");
+        assert_eq!(input(&format!("{} <<< \"a b\"", cat)), "a b\n");
+
+        //  The operators need no whitespace before them.
+        assert_eq!(input("sh<<EOF\necho hello > OUT\nEOF\n"), "hello\n");
+        assert_eq!(input(&format!("{}<<EOF\nhello\nEOF\n", cat)), "hello\n");
+        assert_eq!(input(&format!("{}<<<'a b'<<-A\n\ta\n\tA", cat)), "a\n");
+        //  Quoted, the operator is part of the word.
+        assert_eq!(input("sh -c 'echo \"$1\" > \"$0\"' OUT a'<<'b"), "a<<b\n");
+
+        assert_eq!(run(&mut state, "cat <<"), 2);
+        assert_eq!(run(&mut state, "cat<<"), 2);
+    }
+
+    #[test]
+    fn group_input() {
+        let path = |name: &str| env::temp_dir().join(format!("ysh-group-{}-{}", name, std::process::id()));
+        let (first, rest) = (path("first"), path("rest"));
+        let mut screen = Screen::new(false);
+        let mut state = State::default();
+        let script = format!(
+            "{{ sh -c 'head -n1 > \"$0\"' {}; sh -c 'cat > \"$0\"' {}; }} <<EOF\none\ntwo\nthree\nEOF\n",
+            first.display(),
+            rest.display(),
+        );
+        let script = Script::parse_from(&script).expect("the group parses");
+        state.exec(&script.commands, &mut screen).expect("the group runs");
+        //  The commands read the here-document in turn, rather than each
+        //  reading all of it.
+        assert_eq!(fs::read_to_string(&first).expect("the first line is read"), "one\n");
+        assert_eq!(fs::read_to_string(&rest).expect("the rest is read"), "two\nthree\n");
+        fs::remove_file(&first).expect("the file is removed");
+        fs::remove_file(&rest).expect("the file is removed");
+    }
+
+    #[test]
+    fn exit_status() {
+        //  As for `ysh -c 'exit 3'`.
+        let mut state = State::default();
+        assert_eq!(run(&mut state, "exit 3; x=1"), 3);
+        assert_eq!(state.var("x"), None);
+        assert_eq!(run(&mut State::default(), "false; exit"), 1);
+
+        //  A subshell exits with its status, and the shell goes on.
+        let mut state = State::default();
+        assert_eq!(run(&mut state, "(exit 3)"), 3);
+        assert_eq!(run(&mut state, "(exit 3); true"), 0);
+
+        assert_eq!(run(&mut state, "exit x"), 2);
+        assert_eq!(run(&mut state, "exit 1 2"), 2);
+    }
+
+    #[test]
+    fn input_lines() {
+        let mut screen = Screen::new(false);
+        let mut state = State::default();
+        let mut input: &[u8] = b"x=1; if true\nthen y=2; fi\nexit\nthe rest\n";
+        state.run_input(&mut input, &mut screen).expect("the input runs");
+        assert_eq!((state.var("x"), state.var("y")), (Some("1".into()), Some("2".into())));
+        //  Nothing after `exit` was read.
+        assert_eq!(input, b"the rest\n");
+    }
+
+    #[test]
+    fn variables() {
+        let mut state = State::default();
+        let var = |state: &State, name| state.var(name);
+
+        assert_eq!(run(&mut state, "a=1 b=$a; export c=3 b; readonly r=4"), 0);
+        assert_eq!(var(&state, "b"), Some("1".into()));
+        let env = state.environment();
+        assert_eq!((env.get("b"), env.get("c"), env.get("a")), (Some(&"1"), Some(&"3"), None));
+
+        assert_eq!(run(&mut state, "r=5"), 1);
+        assert_eq!(run(&mut state, "unset a r"), 1);
+        assert_eq!((var(&state, "a"), var(&state, "r")), (None, Some("4".into())));
+
+        //  Assignments in front of a command are only for that command.
+        assert_eq!(run(&mut state, "b=2 true"), 0);
+        assert_eq!(var(&state, "b"), Some("1".into()));
+        assert_eq!(run(&mut state, "r=5 true"), 1);
+
+        run(&mut state, "f() { local b=2; declare -x d=$b; g; }; g() { c=$b$d; }; f");
+        assert_eq!(var(&state, "b"), Some("1".into()));
+        assert_eq!(var(&state, "c"), Some("22".into()));
+        assert_eq!(var(&state, "d"), None);
+        assert_eq!(run(&mut state, "local x"), 1);
+
+        //  `declare -g` reaches the global variable past a local one.
+        run(&mut state, "x=0; f() { local x=1; declare -g x=2 y+=3; declare -gr z=4; e=$x; }; f");
+        assert_eq!(var(&state, "x"), Some("2".into()));
+        assert_eq!(var(&state, "e"), Some("1".into()));
+        assert_eq!(var(&state, "y"), Some("3".into()));
+        assert_eq!(run(&mut state, "z=5"), 1);
+    }
+
+    #[test]
+    fn arith() {
+        let mut state = State::default();
+        assert_eq!(run(&mut state, "  (( x += (1 + 2) ))  "), 0);
+        assert_eq!(state.var("x"), Some("3".into()));
+        assert_eq!(run(&mut state, "((x - 3))"), 1);
+        assert_eq!(run(&mut state, "(( x = 1 << 2 )); y=$x"), 0);
+        assert_eq!(state.var("y"), Some("4".into()));
+        assert_eq!(run(&mut state, "(( x / 0 ))"), 1);
+        //  Parentheses that are not arithmetic are subshells.
+        assert_eq!(run(&mut state, "((x=5) ; (false))"), 1);
+        assert_eq!(state.var("x"), Some("4".into()));
+    }
+
+    #[test]
+    fn arrays() {
+        let mut state = State::default();
+        let elements = |state: &State, name| state.elements(name);
+
+        assert_eq!(run(&mut state, "a=(x 'y z'); a+=(w); a[5]=v; n=${#a[@]} e=${a[-1]}"), 0);
+        assert_eq!(elements(&state, "a"), vec!["x", "y z", "w", "v"]);
+        assert_eq!((state.var("n"), state.var("e")), (Some("4".into()), Some("v".into())));
+        assert_eq!(run(&mut state, "a[0]+=x; unset 'a[1]'; b=(\"${a[@]}\" [9]=u t)"), 0);
+        assert_eq!(elements(&state, "b"), vec!["xx", "w", "v", "u", "t"]);
+        assert_eq!(state.var("a"), Some("xx".into()));
+
+        assert_eq!(run(&mut state, "declare -A m=([k]=1 ['l m']=2); m[k]+=0; v=${m[k]}${m[l m]}"), 0);
+        assert_eq!(state.var("v"), Some("102".into()));
+        assert_eq!(run(&mut state, "m+=(x)"), 1);
+        assert_eq!(run(&mut state, "declare -A a"), 1);
+        assert_eq!(elements(&state, "a"), vec!["xx", "w", "v"]);
+        assert_eq!(run(&mut state, "a=(1) true"), 1);
+
+        //  Arrays are never exported.
+        assert_eq!(run(&mut state, "s=1; export s; declare -a s"), 0);
+        assert_eq!(elements(&state, "s"), vec!["1"]);
+        assert_eq!(state.environment().get("s"), None);
+    }
+
+    #[test]
+    fn positional() {
+        let mut state = State::default();
+
+        assert_eq!(run(&mut state, "set -- a 'b c' d; shift; n=$#"), 0);
+        assert_eq!(state.args, vec!["b c", "d"]);
+        assert_eq!(state.var("n"), Some("2".into()));
+        assert_eq!(run(&mut state, "shift 3"), 1);
+        assert_eq!(state.args.len(), 2);
+
+        //  A function has positional parameters of its own.
+        assert_eq!(run(&mut state, "f() { set -- x; shift; n=$#; }; f \"$@\" e"), 0);
+        assert_eq!(state.var("n"), Some("0".into()));
+        assert_eq!(state.args, vec!["b c", "d"]);
+        assert_eq!(run(&mut state, "set --"), 0);
+        assert!(state.args.is_empty());
+        assert_eq!(run(&mut state, "set -e"), 2);
+    }
+}
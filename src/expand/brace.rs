@@ -0,0 +1,209 @@
+//! Brace expansion
+//!
+//! Brace expansion generates several words from one, and runs before every
+//! other expansion:
+//!
+//! - `a{b,c,d}e` produces `abe ace ade`; alternatives may be empty, and may
+//!   themselves contain braces, as in `{a,b{1,2}}`
+//! - `{1..5}` produces `1 2 3 4 5`, and `{5..1}` counts down
+//! - `{a..e}` produces a range of letters
+//! - `{1..10..3}` steps by 3, producing `1 4 7 10`
+//! - `{01..10}` pads every number with zeroes to the width of the widest end
+//!
+//! A brace group that is neither a list with a comma nor a valid range is left
+//! as it is, so `{}` and `{x}` are ordinary text. Quoted braces, escaped
+//! braces, and the braces of `${name}` never start a brace expansion.
+
+use crate::token;
+
+use super::escape;
+
+/// Expands every brace group in a raw shell word.
+///
+/// The result is always at least one word; a word with no brace groups is
+/// returned unchanged.
+pub fn expand(word: &str) -> Vec<String> {
+    let mut from = 0;
+    while let Some(open) = find_open(word, from) {
+        let (rest, body) = match token::brace(&word[open ..]) {
+            Ok(found) => found,
+            //  An unmatched `{` is literal, as is everything after it.
+            Err(_) => break,
+        };
+        let alternatives = match sequence(body) {
+            Some(items) => items,
+            None => {
+                let parts = split(body);
+                if parts.len() < 2 {
+                    from = open + 1;
+                    continue;
+                }
+                parts.into_iter().flat_map(expand).collect()
+            },
+        };
+        let pre = &word[.. open];
+        let posts = expand(rest);
+        return alternatives.iter()
+            .flat_map(|alt| posts.iter().map(move |post| format!("{}{}{}", pre, alt, post)))
+            .collect();
+    }
+    vec![word.to_string()]
+}
+
+/// Calls `f` with the position of every character in `text` that is not
+/// quoted, escaped, or part of a shell meta-sequence. `f` returns `false` to
+/// stop the scan.
+crate fn scan(text: &str, mut f: impl FnMut(usize, char) -> bool) {
+    let mut rem = text;
+    while let Some(c) = rem.chars().next() {
+        let skip = match c {
+            '\\' => Some(rem.chars().take(2).map(char::len_utf8).sum()),
+            '\'' => token::squote(rem).ok().map(|(r, _)| rem.len() - r.len()),
+            '"' => token::dquote(rem).ok().map(|(r, _)| rem.len() - r.len()),
+            '$' if rem[1 ..].starts_with('(') || rem[1 ..].starts_with('{') => {
+                token::shell_meta(rem).ok().map(|(r, _)| rem.len() - r.len())
+            },
+            _ => None,
+        };
+        match skip {
+            Some(n) => rem = &rem[n ..],
+            None => {
+                if !f(text.len() - rem.len(), c) {
+                    return;
+                }
+                rem = &rem[c.len_utf8() ..];
+            },
+        }
+    }
+}
+
+/// Finds the first unquoted `{` at or after `from`.
+fn find_open(word: &str, from: usize) -> Option<usize> {
+    let mut found = None;
+    scan(&word[from ..], |i, c| {
+        if c == '{' {
+            found = Some(from + i);
+        }
+        found.is_none()
+    });
+    found
+}
+
+/// Splits the body of a brace group at its top-level commas.
+fn split(body: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    scan(body, |i, c| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&body[start .. i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+        true
+    });
+    parts.push(&body[start ..]);
+    parts
+}
+
+/// Expands a range, `x..y` or `x..y..step`, where `x` and `y` are both integers
+/// or both single letters.
+fn sequence(body: &str) -> Option<Vec<String>> {
+    let parts = body.split("..").collect::<Vec<_>>();
+    let step = match parts.len() {
+        2 => 1,
+        3 => parts[2].parse::<i64>().ok()?.checked_abs()?.max(1),
+        _ => return None,
+    };
+    let (lo, hi) = (parts[0], parts[1]);
+
+    if let (Ok(a), Ok(b)) = (lo.parse::<i64>(), hi.parse::<i64>()) {
+        //  A leading zero on either end asks for zero padding.
+        let padded = |s: &str| {
+            let digits = s.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(lo) || padded(hi) { lo.len().max(hi.len()) } else { 0 };
+        return Some(range(a, b, step)?
+            .map(|n| if n < 0 {
+                format!("-{:0w$}", -(n as i128), w = width.saturating_sub(1))
+            } else {
+                format!("{:0w$}", n, w = width)
+            })
+            .collect());
+    }
+
+    let mut lo_chars = lo.chars();
+    let mut hi_chars = hi.chars();
+    let (a, b) = match (lo_chars.next(), lo_chars.next(), hi_chars.next(), hi_chars.next()) {
+        (Some(a), None, Some(b), None) if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+            (a, b)
+        },
+        _ => return None,
+    };
+    //  Between `Z` and `a` lie punctuation characters, which must not be
+    //  mistaken for quotes or wildcards by the later phases.
+    Some(range(a as i64, b as i64, step)?
+        .filter_map(|n| std::char::from_u32(n as u32))
+        .map(|c| escape(&c.to_string()))
+        .collect())
+}
+
+/// Counts from `a` to `b` inclusive, in either direction, by `step`. Returns
+/// `None` if there are too many numbers to count.
+fn range(a: i64, b: i64, step: i64) -> Option<impl Iterator<Item = i64>> {
+    //  The distance between the ends may not fit in an `i64`.
+    let count = (a as i128 - b as i128).abs() / step as i128 + 1;
+    if count > i64::max_value() as i128 {
+        return None;
+    }
+    let step = if a <= b { step as i128 } else { -(step as i128) };
+    Some((0 .. count).map(move |n| (a as i128 + n * step) as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists() {
+        assert_eq!(expand("a{b,c,d}e"), vec!["abe", "ace", "ade"]);
+        assert_eq!(expand("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand("x{a,b{1,2}}"), vec!["xa", "xb1", "xb2"]);
+        assert_eq!(expand("file{,.bak}"), vec!["file", "file.bak"]);
+        assert_eq!(expand("{a}{b,c}"), vec!["{a}b", "{a}c"]);
+    }
+
+    #[test]
+    fn literal() {
+        assert_eq!(expand("{}"), vec!["{}"]);
+        assert_eq!(expand("{x}"), vec!["{x}"]);
+        assert_eq!(expand("{a,b"), vec!["{a,b"]);
+        assert_eq!(expand("'{a,b}'"), vec!["'{a,b}'"]);
+        assert_eq!(expand(r"\{a,b}"), vec![r"\{a,b}"]);
+        assert_eq!(expand("${a,b}"), vec!["${a,b}"]);
+        assert_eq!(expand("$x{a,b}"), vec!["$xa", "$xb"]);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(expand("{1..4}"), vec!["1", "2", "3", "4"]);
+        assert_eq!(expand("{3..1}"), vec!["3", "2", "1"]);
+        assert_eq!(expand("{1..10..4}"), vec!["1", "5", "9"]);
+        assert_eq!(expand("{-1..1}"), vec!["-1", "0", "1"]);
+        assert_eq!(expand("{08..11}"), vec!["08", "09", "10", "11"]);
+        assert_eq!(expand("{a..c}"), vec!["a", "b", "c"]);
+        assert_eq!(expand("x{c..a..2}"), vec!["xc", "xa"]);
+        assert_eq!(expand("{1..b}"), vec!["{1..b}"]);
+        assert_eq!(expand("{-9223372036854775808..1}"), vec!["{-9223372036854775808..1}"]);
+        assert_eq!(
+            expand("{-9223372036854775808..-9223372036854775807}"),
+            vec!["-9223372036854775808", "-9223372036854775807"],
+        );
+        assert_eq!(expand("{9223372036854775807..9223372036854775806..3}"), vec!["9223372036854775807"]);
+    }
+}
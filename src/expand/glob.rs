@@ -0,0 +1,291 @@
+//! Pathname expansion
+//!
+//! A word containing an unquoted `*`, `?`, or `[` is a pattern, and is replaced
+//! by the sorted list of paths that it matches:
+//!
+//! - `*` matches any run of characters within one path component
+//! - `?` matches any one character
+//! - `[abc]` matches one of the listed characters, and may hold ranges such as
+//!   `[a-z]`; `[!abc]` or `[^abc]` matches any character not listed
+//! - `**` as a whole path component matches zero or more directories
+//! - `\c` matches `c` literally
+//!
+//! Names beginning with `.` are only matched by a pattern component that also
+//! begins with `.`, unless the `dotglob` option is set. The `/` separating
+//! path components is never matched by a wildcard.
+//!
+//! Patterns reach this module with their quoted characters already escaped
+//! with a backslash, so a quoted `*` is just another literal character here.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Reports whether `pattern` holds any unescaped wildcard characters.
+pub fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => drop(chars.next()),
+            '*' | '?' | '[' => return true,
+            _ => {},
+        }
+    }
+    false
+}
+
+/// Removes the backslash escapes from a pattern with no wildcards in it.
+pub fn unescape(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Matches a single path component against a single pattern component.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pat = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    //  The most recent `*`, and the name position it is currently matched up
+    //  to. On a mismatch, the star is extended by one character and matching
+    //  resumes after it.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match pat.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            },
+            Some('?') => Some(p + 1),
+            Some('[') => match class(&pat[p ..], name[n]) {
+                Some((true, len)) => Some(p + len),
+                Some((false, _)) => None,
+                //  An unterminated `[` is an ordinary character.
+                None if name[n] == '[' => Some(p + 1),
+                None => None,
+            },
+            Some('\\') if p + 1 < pat.len() => {
+                if pat[p + 1] == name[n] { Some(p + 2) } else { None }
+            },
+            Some(&c) => if c == name[n] { Some(p + 1) } else { None },
+            None => None,
+        };
+        match (step, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            },
+            (None, Some((sp, sn))) => {
+                star = Some((sp, sn + 1));
+                p = sp + 1;
+                n = sn + 1;
+            },
+            (None, None) => return false,
+        }
+    }
+    pat[p ..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the bracket expression at the start of `pat`.
+///
+/// Returns whether it matched and the length of the expression, or `None` if
+/// the expression is not terminated.
+fn class(pat: &[char], c: char) -> Option<(bool, usize)> {
+    //  Reads one member character, which may be escaped, and moves past it.
+    let read = |i: &mut usize| -> Option<char> {
+        if pat.get(*i) == Some(&'\\') {
+            *i += 1;
+        }
+        let ch = *pat.get(*i)?;
+        *i += 1;
+        Some(ch)
+    };
+    let mut i = 1;
+    let negate = match pat.get(i) {
+        Some('!') | Some('^') => {
+            i += 1;
+            true
+        },
+        _ => false,
+    };
+    let start = i;
+    let mut found = false;
+    loop {
+        //  A `]` right after the opening is a member, not the end.
+        if pat.get(i) == Some(&']') && i > start {
+            return Some((found != negate, i + 1));
+        }
+        let lo = read(&mut i)?;
+        if pat.get(i) == Some(&'-') && pat.get(i + 1).map_or(false, |&ch| ch != ']') {
+            i += 1;
+            let hi = read(&mut i)?;
+            found |= lo <= c && c <= hi;
+        } else {
+            found |= lo == c;
+        }
+    }
+}
+
+/// Expands a pattern into the sorted list of paths it matches.
+///
+/// Relative patterns are matched in `cwd`, and the results are written as the
+/// pattern was: relative patterns produce relative paths.
+pub fn glob(pattern: &str, cwd: &Path, dotglob: bool) -> Vec<String> {
+    let (base, text, pattern) = if pattern.starts_with('/') {
+        (PathBuf::from("/"), "/".to_string(), pattern.trim_start_matches('/'))
+    } else {
+        (cwd.to_path_buf(), String::new(), pattern)
+    };
+    //  A trailing slash only matches directories, and is kept on the results.
+    let dirs_only = pattern.ends_with('/');
+    let parts = pattern.split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut found = Vec::new();
+    walk(&base, text, &parts, dotglob, &mut found);
+    let mut found = found.into_iter()
+        .filter(|(path, _)| !dirs_only || path.is_dir())
+        .map(|(_, text)| if dirs_only { text + "/" } else { text })
+        .collect::<Vec<_>>();
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// Matches the pattern components `parts` beneath the directory `dir`, which
+/// the user wrote as `text`, and collects every match into `found`.
+fn walk(dir: &Path, text: String, parts: &[&str], dotglob: bool, found: &mut Vec<(PathBuf, String)>) {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => {
+            if !text.is_empty() {
+                found.push((dir.to_path_buf(), text));
+            }
+            return;
+        },
+    };
+    let join = |name: &str| match text.as_str() {
+        "" => name.to_string(),
+        t if t.ends_with('/') => format!("{}{}", t, name),
+        t => format!("{}/{}", t, name),
+    };
+
+    if *part == "**" {
+        //  `**` may match no directories at all...
+        walk(dir, text.clone(), rest, dotglob, found);
+        //  ...or any directory below this one, in which case it remains in
+        //  force for the next level down. Symbolic links are not followed, so
+        //  that a link to a parent directory cannot recurse forever.
+        for (name, path) in entries(dir, dotglob) {
+            let is_dir = fs::symlink_metadata(&path)
+                .map(|m| m.file_type().is_dir())
+                .unwrap_or(false);
+            if is_dir {
+                walk(&path, join(&name), parts, dotglob, found);
+            } else if rest.is_empty() {
+                found.push((path, join(&name)));
+            }
+        }
+    } else if !has_magic(part) {
+        let name = unescape(part);
+        let path = dir.join(&name);
+        if fs::symlink_metadata(&path).is_ok() {
+            walk(&path, join(&name), rest, dotglob, found);
+        }
+    } else {
+        let hidden = dotglob || part.starts_with('.');
+        for (name, path) in entries(dir, hidden) {
+            //  Unlike `**`, a pattern goes down one level only, so it can follow
+            //  a symbolic link to a directory without any danger of a loop.
+            if matches(part, &name) && (rest.is_empty() || path.is_dir()) {
+                walk(&path, join(&name), rest, dotglob, found);
+            }
+        }
+    }
+}
+
+/// Lists the entries of a directory, leaving out hidden ones unless `hidden`.
+///
+/// Unreadable directories and names that are not valid UTF-8 are skipped.
+fn entries(dir: &Path, hidden: bool) -> Vec<(String, PathBuf)> {
+    fs::read_dir(dir)
+        .map(|rd| rd.filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                Some((name, entry.path()))
+            })
+            .filter(|(name, _)| hidden || !name.starts_with('.'))
+            .collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn patterns() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("m??n.rs", "main.rs"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b", "xxaxxbxx"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "x"));
+        assert!(matches("[abc", "[abc"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn magic() {
+        assert!(has_magic("*.rs"));
+        assert!(has_magic("src/[ab]"));
+        assert!(!has_magic(r"\*.rs"));
+        assert!(!has_magic("plain"));
+        assert_eq!(unescape(r"a\*b\\"), r"a*b\");
+    }
+
+    #[test]
+    fn tree() {
+        let root = env::temp_dir().join(format!("ysh-glob-{}", std::process::id()));
+        for dir in &["src/ast", "src/.hidden"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &["src/lib.rs", "src/ast/mod.rs", "src/.hidden/x.rs", "README.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        assert_eq!(glob("src/*.rs", &root, false), vec!["src/lib.rs"]);
+        assert_eq!(glob("**/*.rs", &root, false), vec!["src/ast/mod.rs", "src/lib.rs"]);
+        assert_eq!(
+            glob("**/*.rs", &root, true),
+            vec!["src/.hidden/x.rs", "src/ast/mod.rs", "src/lib.rs"],
+        );
+        assert_eq!(glob("*/", &root, false), vec!["src/"]);
+        assert_eq!(glob("src/.*", &root, false), vec!["src/.hidden"]);
+        assert!(glob("*.txt", &root, false).is_empty());
+
+        //  A link to a directory is followed by a pattern, but not by `**`.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("src"), root.join("link")).unwrap();
+            assert_eq!(glob("*/ast/*.rs", &root, false), vec!["link/ast/mod.rs", "src/ast/mod.rs"]);
+            assert_eq!(glob("**/mod.rs", &root, false), vec!["src/ast/mod.rs"]);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,760 @@
+//! Expansions
+//!
+//! Between parsing a command and running it, the shell rewrites its words:
+//! `~` becomes a home directory, `*.rs` becomes a list of files, and so on.
+//! Each kind of rewriting is a separate phase, and the phases run in the order
+//! defined by POSIX:
+//!
+//! 1. brace expansion
+//! 1. tilde expansion
+//! 1. parameter and arithmetic expansion
+//! 1. quote removal
+//! 1. pathname expansion (globbing)
+//!
+//! Process substitution, which POSIX does not define, comes before all of
+//! these, and works on the whole command line (see `process`).
+//!
+//! The phases work on raw shell words (see `token::shell_word`), which still
+//! carry their quotes, because each phase only applies to unquoted text.
+
+use std::{borrow::Cow, mem, path::PathBuf};
+
+use failure::Fail;
+
+use crate::{
+    arith::{self, ArithError},
+    ast::{Here, StackIndex},
+    st::{self, State, Subscript, Value},
+    token,
+};
+
+use self::param::{Op, Param};
+
+pub mod brace;
+pub mod glob;
+pub mod param;
+pub mod process;
+
+#[derive(Clone, Debug, Fail)]
+pub enum ExpandError {
+    #[fail(display = "no match: {}", _0)]
+    NoMatch(String),
+    #[fail(display = "{}", _0)]
+    Arith(#[cause] ArithError),
+    #[fail(display = "{}: bad substitution", _0)]
+    BadSubstitution(String),
+    #[fail(display = "process substitution: {}", _0)]
+    Process(String),
+    #[fail(display = "{}: bad array subscript", _0)]
+    BadSubscript(String),
+}
+
+impl From<ArithError> for ExpandError {
+    fn from(err: ArithError) -> Self {
+        ExpandError::Arith(err)
+    }
+}
+
+/// Expands raw shell words into the final arguments for a command.
+///
+/// A single word may expand to any number of arguments: a brace group
+/// produces one argument per alternative, and a glob pattern
+/// produces one argument per matching path. When a pattern matches nothing it
+/// is kept as written, unless the `nullglob` option removes it or the
+/// `failglob` option makes it an error.
+///
+/// The state is mutable because arithmetic expansions may assign variables.
+/// The values of unquoted expansions are split into fields at the characters
+/// of `$IFS`, and each field may still be a glob pattern. Inside double
+/// quotes, only `"$@"` and `"${arr[@]}"` make several fields, one for each
+/// positional parameter or element.
+pub fn words<'a>(
+    words: impl IntoIterator<Item = &'a str>,
+    state: &mut State,
+) -> Result<Vec<String>, ExpandError> {
+    let mut out = Vec::new();
+    for word in words.into_iter().flat_map(brace::expand) {
+        let word = tilde_word(&word, state);
+        for field in substitute(&word, Mode::Fields, state)? {
+            let (text, pattern) = unquote(&field);
+            if !glob::has_magic(&pattern) {
+                out.push(text);
+                continue;
+            }
+            let found = glob::glob(&pattern, &state.pwd, state.options.dotglob);
+            if !found.is_empty() {
+                out.extend(found);
+            } else if state.options.failglob {
+                return Err(ExpandError::NoMatch(text));
+            } else if !state.options.nullglob {
+                out.push(text);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Expands a raw word into exactly one string, without brace or pathname
+/// expansion, as for the word of a `case` command.
+pub fn word(word: &str, state: &mut State) -> Result<String, ExpandError> {
+    let word = tilde_word(word, state);
+    let word = substitute(&word, Mode::Word, state)?.join(" ");
+    Ok(unquote(&word).0)
+}
+
+/// Expands a raw word into a glob pattern, as for the patterns of a `case`
+/// command. Quoted wildcard characters are escaped in the pattern.
+pub fn pattern(word: &str, state: &mut State) -> Result<String, ExpandError> {
+    let word = tilde_word(word, state);
+    let word = substitute(&word, Mode::Word, state)?.join(" ");
+    Ok(unquote(&word).1)
+}
+
+/// Expands a here-document or here-string into the text for a command's
+/// standard input.
+///
+/// A here-string is expanded as a single word, without brace or pathname
+/// expansion, and ends with a newline. A here-document with an unquoted
+/// delimiter has its parameters and arithmetic expanded, and its backslashes
+/// only escape `$`, `` ` ``, `\`, and newlines; quotes in it are ordinary
+/// characters.
+pub fn here(here: &Here, state: &mut State) -> Result<String, ExpandError> {
+    match here {
+        Here::Str(text) => Ok(word(text, state)? + "\n"),
+        Here::Doc { body, expand: false } => Ok(body.clone()),
+        Here::Doc { body, expand: true } => {
+            Ok(substitute_text(body, state)?.into_owned())
+        },
+    }
+}
+
+/// Evaluates an arithmetic expression, as for `$((expression))` and the
+/// `((expression))` command. Parameters in the expression are expanded before
+/// it is evaluated.
+pub fn evaluate(expr: &str, state: &mut State) -> Result<i64, ExpandError> {
+    let expr = substitute_text(expr, state)?;
+    Ok(arith::eval(&expr, state)?)
+}
+
+/// Performs tilde expansion on a raw word.
+///
+/// Only an unquoted tilde prefix is expanded, and the directory it expands to
+/// is escaped so that later phases take it literally.
+fn tilde_word<'a>(word: &'a str, state: &State) -> Cow<'a, str> {
+    let end = word.find('/').unwrap_or(word.len());
+    if word[.. end].contains(|c| c == '\'' || c == '"' || c == '\\') {
+        return Cow::Borrowed(word);
+    }
+    match tilde(&word[.. end], state) {
+        Cow::Owned(dir) => Cow::Owned(escape(&dir) + &word[end ..]),
+        Cow::Borrowed(_) => Cow::Borrowed(word),
+    }
+}
+
+/// What quotes and backslashes mean in a text that is being expanded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    /// A raw shell word, which keeps its quotes for quote removal. Expanded
+    /// values are escaped so that quote removal gives them back unchanged.
+    Word,
+    /// A raw shell word, as for `Word`, whose unquoted expansions are also
+    /// split into fields, as the arguments of a command are.
+    Fields,
+    /// The body of a here-document, or an arithmetic expression: quotes are
+    /// ordinary characters, backslashes only escape `$`, `` ` ``, `\`, and
+    /// newlines, and the result is final.
+    Text,
+}
+
+/// Replaces every parameter expansion, `$name` or `${name}`, and every
+/// arithmetic expansion, `$((expression))`, in a text with its value.
+///
+/// In a word, expansions are found outside of quotes and inside double quotes,
+/// but not inside single quotes or after a backslash. The expansions are
+/// performed from left to right, so each sees the assignments made by those
+/// before it.
+///
+/// Text is always one field, but a word is split into several by `$@` and
+/// `${arr[@]}`, and by `$*` and `${arr[*]}` outside of double quotes. Each
+/// positional parameter or element starts a new field, which is still inside
+/// double quotes if the expansion was. When there are none, a field left with
+/// nothing but the quotes around them is removed, so that `"$@"` becomes no
+/// fields at all. In `Mode::Fields`, unquoted values are split further (see
+/// `split`), and a field that is left empty without any quotes is removed.
+fn substitute<'a>(
+    text: &'a str,
+    mode: Mode,
+    state: &mut State,
+) -> Result<Vec<Cow<'a, str>>, ExpandError> {
+    let mut fields = Vec::new();
+    let mut out = String::new();
+    //  The text up to `done` has been copied to `out`.
+    let mut done = 0;
+    let mut in_dquote = false;
+    //  Set when the field has had an expansion of no fields.
+    let mut vanish = false;
+    let mut rem = text;
+    while let Some(c) = rem.chars().next() {
+        let at = text.len() - rem.len();
+        if c == '$' && mode != Mode::Text {
+            if let Some((rest, all)) = all_fields(rem, in_dquote, state)? {
+                out.push_str(&text[done .. at]);
+                let ifs = ifs(mode, in_dquote, state);
+                for (n, arg) in all.iter().enumerate() {
+                    match ifs {
+                        Some(ref ifs) => {
+                            if n > 0 && !out.is_empty() {
+                                fields.push(Cow::Owned(mem::replace(&mut out, String::new())));
+                            }
+                            split(arg, ifs, &mut out, &mut fields);
+                            continue;
+                        },
+                        None if n > 0 => {
+                            if in_dquote {
+                                out.push('"');
+                            }
+                            fields.push(Cow::Owned(mem::replace(&mut out, String::new())));
+                            if in_dquote {
+                                out.push('"');
+                            }
+                        },
+                        None => {},
+                    }
+                    out.push_str(&quote(arg, in_dquote));
+                }
+                vanish |= all.is_empty();
+                done = text.len() - rest.len();
+                rem = rest;
+                continue;
+            }
+        }
+        //  Each step skips some of the text, or replaces it with a value.
+        let (rest, value) = match c {
+            '\\' if mode == Mode::Text => match rem[1 ..].chars().next() {
+                //  An escaped newline joins the lines.
+                Some('\n') => (&rem[2 ..], Some(String::new())),
+                Some(n) if "$`\\".contains(n) => (&rem[2 ..], Some(n.to_string())),
+                _ => (&rem[1 ..], None),
+            },
+            '\\' => {
+                let mut chars = rem.chars();
+                chars.next();
+                chars.next();
+                (chars.as_str(), None)
+            },
+            '\'' if mode != Mode::Text && !in_dquote => {
+                (token::squote(rem).map(|(r, _)| r).unwrap_or(""), None)
+            },
+            '"' if mode != Mode::Text => {
+                in_dquote = !in_dquote;
+                (&rem[1 ..], None)
+            },
+            '$' => match dollar(rem, state)? {
+                Some((rest, value)) => match ifs(mode, in_dquote, state) {
+                    Some(ifs) => {
+                        out.push_str(&text[done .. at]);
+                        split(&value, &ifs, &mut out, &mut fields);
+                        done = text.len() - rest.len();
+                        rem = rest;
+                        continue;
+                    },
+                    None if mode == Mode::Text => (rest, Some(value)),
+                    None => (rest, Some(quote(&value, in_dquote))),
+                },
+                None => (&rem[1 ..], None),
+            },
+            c => (&rem[c.len_utf8() ..], None),
+        };
+        if let Some(value) = value {
+            out.push_str(&text[done .. at]);
+            out.push_str(&value);
+            done = text.len() - rest.len();
+        }
+        rem = rest;
+    }
+    if done == 0 {
+        fields.push(Cow::Borrowed(text));
+        return Ok(fields);
+    }
+    out.push_str(&text[done ..]);
+    let removed = match mode {
+        Mode::Fields => out.is_empty(),
+        _ => false,
+    };
+    if !removed && !(vanish && out.chars().all(|c| c == '"')) {
+        fields.push(Cow::Owned(out));
+    }
+    Ok(fields)
+}
+
+/// Gets the characters at which the value of an expansion is split, if it is
+/// split at all: only outside of double quotes, when `$IFS` is not empty. It
+/// is a space, a tab, and a newline when it is unset.
+fn ifs(mode: Mode, in_dquote: bool, state: &State) -> Option<String> {
+    if mode != Mode::Fields || in_dquote {
+        return None;
+    }
+    match state.var("IFS") {
+        Some(ref ifs) if ifs.is_empty() => None,
+        Some(ifs) => Some(ifs),
+        None => Some(" \t\n".to_string()),
+    }
+}
+
+/// Splits the value of an unquoted expansion into fields, adding them to a
+/// word: the first piece of the value goes on the end of the field being
+/// built, in `out`, and the last stays there for the rest of the word.
+///
+/// The pieces are separated by the characters of `ifs`. A run of whitespace in
+/// `ifs` separates two pieces, and is ignored at the start of a field, so
+/// whitespace at the ends of the value only ends and begins fields. Any other
+/// character of `ifs`, with the `ifs` whitespace around it, separates two
+/// pieces as well, even if they are empty, so that `a::b` is three fields when
+/// `:` is in `ifs`.
+fn split<'a>(value: &str, ifs: &str, out: &mut String, fields: &mut Vec<Cow<'a, str>>) {
+    let white = |c: char| c.is_whitespace() && ifs.contains(c);
+    let mut chars = value.char_indices().peekable();
+    //  The start of the piece that is being read.
+    let mut start = 0;
+    while let Some((at, c)) = chars.next() {
+        if !ifs.contains(c) {
+            continue;
+        }
+        out.push_str(&quote(&value[start .. at], false));
+        let mut hard = !white(c);
+        while let Some(&(_, n)) = chars.peek() {
+            if white(n) || (!hard && ifs.contains(n)) {
+                hard |= !white(n);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        start = chars.peek().map_or(value.len(), |&(at, _)| at);
+        if hard || !out.is_empty() {
+            fields.push(Cow::Owned(mem::replace(out, String::new())));
+        }
+    }
+    out.push_str(&quote(&value[start ..], false));
+}
+
+/// Performs parameter and arithmetic expansion on text that is not a word,
+/// which always stays one field.
+fn substitute_text<'a>(text: &'a str, state: &mut State) -> Result<Cow<'a, str>, ExpandError> {
+    let mut fields = substitute(text, Mode::Text, state)?;
+    Ok(fields.pop().unwrap_or_default())
+}
+
+/// Finds an expansion of every positional parameter or every element of an
+/// array as separate fields, `$@` or `${arr[@]}`, or the same with `*` outside
+/// of double quotes, at the start of a text. Returns the rest of the text and
+/// the fields.
+fn all_fields<'a>(
+    text: &'a str,
+    in_dquote: bool,
+    state: &mut State,
+) -> Result<Option<(&'a str, Vec<String>)>, ExpandError> {
+    let (rest, param) = match text[1 ..].chars().next() {
+        Some('@') | Some('*') => {
+            (&text[2 ..], Param { name: &text[1 .. 2], subscript: None, op: None })
+        },
+        Some('{') => match token::shell_meta(text) {
+            Ok((rest, meta)) if meta.starts_with('{') => {
+                match Param::parse(&meta[1 .. meta.len() - 1]) {
+                    Some(param) => (rest, param),
+                    None => return Ok(None),
+                }
+            },
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    //  A count, like `${#arr[@]}`, is a single field.
+    if param.op == Some(Op::Length) {
+        return Ok(None);
+    }
+    match param.all() {
+        Some("@") => Ok(Some((rest, param.expand(state)?))),
+        Some("*") if !in_dquote => Ok(Some((rest, param.expand(state)?))),
+        _ => Ok(None),
+    }
+}
+
+/// Works out which element of an array a subscript names.
+///
+/// The subscript of an associative array is expanded like a word, and that of
+/// an indexed array is an arithmetic expression. A negative index counts back
+/// from the end of the array.
+pub fn subscript(name: &str, sub: &str, state: &mut State) -> Result<Subscript, ExpandError> {
+    if let Some(Value::Assoc(_)) = state.value(name) {
+        return Ok(Subscript::Key(word(sub, state)?));
+    }
+    let n = evaluate(sub, state)?;
+    if n >= 0 {
+        return Ok(Subscript::Index(n as usize));
+    }
+    let end = state.value(name).map_or(0, Value::end) as i64;
+    if end + n < 0 {
+        return Err(ExpandError::BadSubscript(format!("{}[{}]", name, sub)));
+    }
+    Ok(Subscript::Index((end + n) as usize))
+}
+
+/// The names of the special parameters, which are a single character.
+const SPECIAL: &str = "@*#?$!-";
+
+/// Expands the parameter or arithmetic expansion at the start of a text, which
+/// begins with `$`.
+///
+/// Returns the rest of the text and the value, or `None` if the `$` does not
+/// begin an expansion and is an ordinary character. An unset parameter
+/// expands to nothing.
+fn dollar<'a>(
+    text: &'a str,
+    state: &mut State,
+) -> Result<Option<(&'a str, String)>, ExpandError> {
+    let rem = &text[1 ..];
+    if let Ok((rest, expr)) = token::arith(rem) {
+        return Ok(Some((rest, evaluate(expr, state)?.to_string())));
+    }
+    let (rest, name) = match rem.chars().next() {
+        Some('{') => match token::shell_meta(text) {
+            Ok((rest, meta)) if meta.starts_with('{') => {
+                let body = &meta[1 .. meta.len() - 1];
+                let param = Param::parse(body)
+                    .ok_or_else(|| ExpandError::BadSubstitution(format!("${{{}}}", body)))?;
+                let fields = param.expand(state)?;
+                let value = match param.all() {
+                    Some("*") if param.op != Some(Op::Length) => state.join_fields(&fields),
+                    _ => fields.join(" "),
+                };
+                return Ok(Some((rest, value)));
+            },
+            _ => return Ok(None),
+        },
+        //  Only one digit is a positional parameter: `$10` is `${1}0`.
+        Some(c) if c.is_ascii_digit() || SPECIAL.contains(c) => (&rem[1 ..], &rem[.. 1]),
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            let end = rem.find(|c: char| !(c == '_' || c.is_ascii_alphanumeric()))
+                .unwrap_or(rem.len());
+            (&rem[end ..], &rem[.. end])
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some((rest, state.param(name).unwrap_or_default())))
+}
+
+/// Escapes the value of an expansion in a word, so that quote removal gives it
+/// back unchanged. Outside of double quotes, wildcards are left as they are,
+/// because an unquoted expansion is still subject to pathname expansion.
+fn quote(value: &str, in_dquote: bool) -> String {
+    let special = if in_dquote { "\\\"$`" } else { "\\'\"$`" };
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Escapes text with backslashes so that it is taken literally by quote
+/// removal and pathname expansion.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_whitespace() || "\\'\"$`*?[]~".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Removes the quotes from a raw word.
+///
+/// Returns the word's text, and the same text as a glob pattern in which every
+/// quoted character that would otherwise be a wildcard is escaped with a
+/// backslash.
+///
+/// - Within single quotes, every character is literal.
+/// - Within double quotes, a backslash only escapes `$`, `` ` ``, `"`, `\`, or
+///   a newline; before anything else it is an ordinary character.
+/// - Outside of quotes, a backslash makes the next character literal.
+pub fn unquote(word: &str) -> (String, String) {
+    let mut text = String::with_capacity(word.len());
+    let mut pattern = String::with_capacity(word.len());
+    let quoted = |c: char, text: &mut String, pattern: &mut String| {
+        if "*?[]\\".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+        text.push(c);
+    };
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                while let Some(c) = chars.next() {
+                    if c == '\'' {
+                        break;
+                    }
+                    quoted(c, &mut text, &mut pattern);
+                }
+            },
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.peek() {
+                            Some(&n) if "$`\"\\\n".contains(n) => {
+                                chars.next();
+                                quoted(n, &mut text, &mut pattern);
+                            },
+                            _ => quoted('\\', &mut text, &mut pattern),
+                        },
+                        c => quoted(c, &mut text, &mut pattern),
+                    }
+                }
+            },
+            '\\' => if let Some(c) = chars.next() {
+                quoted(c, &mut text, &mut pattern);
+            },
+            c => {
+                text.push(c);
+                pattern.push(c);
+            },
+        }
+    }
+    (text, pattern)
+}
+
+/// Performs tilde expansion on a word.
+///
+/// A word beginning with `~` has a *tilde prefix*: everything up to the first
+/// `/`, or the whole word if there is none. The prefix is replaced by:
+///
+/// - `~` alone: the user's home directory
+/// - `~+`: the working directory
+/// - `~-`: the previous working directory, `$OLDPWD`
+/// - `~N`, `~+N`, or `~-N`: an entry in the directory stack, as `dirs -v` would
+///   number it with `+N` and `-N`
+/// - `~name`: the home directory of the user `name`
+///
+/// If the replacement cannot be found, the word is left as it is.
+pub fn tilde<'a>(word: &'a str, state: &State) -> Cow<'a, str> {
+    if !word.starts_with('~') {
+        return Cow::Borrowed(word);
+    }
+    let (prefix, rest) = match word.find('/') {
+        Some(n) => word.split_at(n),
+        None => (word, ""),
+    };
+    let dir = match &prefix[1 ..] {
+        "" => state.home(),
+        "+" => Some(state.pwd.clone()),
+        "-" => state.var("OLDPWD").map(PathBuf::from),
+        n if n.starts_with(|c: char| c.is_ascii_digit()) => {
+            stack_entry(state, &format!("+{}", n))
+        },
+        n if n.starts_with('+') || n.starts_with('-') => stack_entry(state, n),
+        name => st::home_of(name),
+    };
+    match dir {
+        Some(dir) => Cow::Owned(format!("{}{}", dir.display(), rest)),
+        None => Cow::Borrowed(word),
+    }
+}
+
+/// Looks up a `+N` or `-N` entry in the directory stack.
+fn stack_entry(state: &State, index: &str) -> Option<PathBuf> {
+    let stack = state.dir_stack();
+    StackIndex::parse(index)?
+        .resolve(stack.len())
+        .map(|n| stack[n].to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::st::Var;
+
+    #[test]
+    fn quotes() {
+        assert_eq!(unquote("plain"), ("plain".into(), "plain".into()));
+        assert_eq!(unquote("*.rs"), ("*.rs".into(), "*.rs".into()));
+        assert_eq!(unquote("'*'.rs"), ("*.rs".into(), "\\*.rs".into()));
+        assert_eq!(unquote("\"a b\"*"), ("a b*".into(), "a b*".into()));
+        assert_eq!(unquote(r#""\$x \y""#), (r"$x \y".into(), r"$x \\y".into()));
+        assert_eq!(unquote(r"a\ b\*"), ("a b*".into(), r"a b\*".into()));
+    }
+
+    #[test]
+    fn quoted_tilde() {
+        let mut state = State::default();
+        assert_eq!(words(vec!["'~'", "\"~\"/x"], &mut state).unwrap(), vec!["~", "~/x"]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut state = State::default();
+        assert_eq!(
+            words(vec!["$((1 + 2))", "x$(( (1 + 2) * 3 ))y", "\"$((6 / 2))\"", "'$((1))'"], &mut state)
+                .unwrap(),
+            vec!["3", "x9y", "3", "$((1))"],
+        );
+        assert_eq!(words(vec![r"\$((1))"], &mut state).unwrap(), vec!["$((1))"]);
+        assert!(words(vec!["$((1 / 0))"], &mut state).is_err());
+    }
+
+    #[test]
+    fn parameters() {
+        let var = |value: &str| Var { value: Some(Value::Scalar(value.to_string())), ..Var::default() };
+        let mut locals = BTreeMap::new();
+        locals.insert("x".to_string(), var("a*'b"));
+        locals.insert("n".to_string(), var("4"));
+        locals.insert("unset".to_string(), Var::default());
+        let mut state = State {
+            args: vec!["one".into(), "two words".into()],
+            locals: vec![locals],
+            ..State::default()
+        };
+        assert_eq!(
+            words(vec!["$1", "\"$2\"", "[$#]", "$@", "'$1'", "${n}0", "$10", "$unset.", "$"], &mut state)
+                .unwrap(),
+            vec!["one", "two words", "[2]", "one", "two", "words", "$1", "40", "one0", ".", "$"],
+        );
+        assert_eq!(words(vec!["\"$x\"", "\\$x"], &mut state).unwrap(), vec!["a*'b", "$x"]);
+        assert_eq!(words(vec!["$(( $n * 2 + n ))"], &mut state).unwrap(), vec!["12"]);
+        assert!(words(vec!["${a-b}"], &mut state).is_err());
+        let doc = Here::Doc { body: "'$x' \\$n $2\n".into(), expand: true };
+        assert_eq!(here(&doc, &mut state).unwrap(), "'a*'b' $n two words\n");
+    }
+
+    #[test]
+    fn positional() {
+        let mut state = State {
+            args: vec!["a b".into(), "c".into()],
+            ..State::default()
+        };
+        let expand = |state: &mut State, text: &str| words(text.split(' '), state).unwrap();
+        assert_eq!(expand(&mut state, "\"$@\""), vec!["a b", "c"]);
+        assert_eq!(expand(&mut state, "x\"<$@>\"y"), vec!["x<a b", "c>y"]);
+        assert_eq!(expand(&mut state, "\"${@}\" $*"), vec!["a b", "c", "a", "b", "c"]);
+        assert_eq!(expand(&mut state, "\"$*\""), vec!["a b c"]);
+        assert_eq!(word("\"$@\"", &mut state).unwrap(), "a b c");
+        state.set_var("IFS", ",".into()).expect("IFS is not readonly");
+        assert_eq!(expand(&mut state, "\"$*\""), vec!["a b,c"]);
+
+        state.args.clear();
+        assert_eq!(expand(&mut state, "\"$@\" $@ \"$@\"'' [$*]"), vec!["", "[]"]);
+        assert_eq!(expand(&mut state, "$# \"$-\" $!"), vec!["0", ""]);
+        assert_eq!(expand(&mut state, "$$"), vec![std::process::id().to_string()]);
+    }
+
+    #[test]
+    fn splitting() {
+        let mut state = State {
+            args: vec![" a  b ".into(), "".into(), "c".into()],
+            ..State::default()
+        };
+        state.set_var("x", "  one\ttwo\n *.none ".into()).expect("x is not readonly");
+        state.set_var("empty", "".into()).expect("empty is not readonly");
+        let expand = |state: &mut State, text: &str| words(text.split(' '), state).unwrap();
+        assert_eq!(expand(&mut state, "<$x> \"$x\""), vec!["<", "one", "two", "*.none", ">", "  one\ttwo\n *.none "]);
+        assert_eq!(expand(&mut state, "$empty \"$empty\" ''$empty $@"), vec!["", "", "a", "b", "c"]);
+        assert_eq!(word("$x", &mut state).unwrap(), "  one\ttwo\n *.none ");
+
+        //  Other characters of `IFS` separate empty fields too.
+        state.set_var("IFS", ": ".into()).expect("IFS is not readonly");
+        state.set_var("path", ":a : b::c:".into()).expect("path is not readonly");
+        assert_eq!(expand(&mut state, "$path"), vec!["", "a", "b", "", "c"]);
+        assert_eq!(expand(&mut state, "x$path"), vec!["x", "a", "b", "", "c"]);
+        state.set_var("IFS", "".into()).expect("IFS is not readonly");
+        assert_eq!(expand(&mut state, "$x $empty"), vec!["  one\ttwo\n *.none "]);
+    }
+
+    #[test]
+    fn arrays() {
+        let mut state = State::default();
+        let elements = vec![(0, "a b"), (1, "c"), (4, "d")];
+        let indexed = Value::Indexed(elements.into_iter().map(|(n, v)| (n, v.to_string())).collect());
+        *state.value_mut("arr").expect("arr is not readonly") = Some(indexed);
+        let mut assoc = Value::Assoc(BTreeMap::new());
+        assoc.set_element(Subscript::Key("k 1".into()), "v".into());
+        *state.value_mut("map").expect("map is not readonly") = Some(assoc);
+        let expand = |state: &mut State, text: &str| words(text.split(' '), state).unwrap();
+        assert_eq!(expand(&mut state, "\"${arr[@]}\""), vec!["a b", "c", "d"]);
+        assert_eq!(expand(&mut state, "${#arr[@]} ${arr[1+3]} ${arr[-2]} $arr"), vec!["3", "d", "a", "b"]);
+        assert_eq!(expand(&mut state, "\"${arr[*]}\" ${none[@]}"), vec!["a b c d"]);
+        assert_eq!(word("${map[k 1]}${map[k]}", &mut state).unwrap(), "v");
+        assert!(word("${arr[-9]}", &mut state).is_err());
+    }
+
+    #[test]
+    fn operators() {
+        let mut state = State {
+            args: vec!["one".into(), "two".into(), "three".into()],
+            ..State::default()
+        };
+        state.set_var("path", "/usr/lib/x.tar.gz".into()).expect("path is not readonly");
+        state.set_var("star", "*".into()).expect("star is not readonly");
+        let expand = |state: &mut State, text: &str| words(text.split(' '), state).unwrap();
+        assert_eq!(
+            expand(&mut state, "${#path} ${path##*/} ${path%.*} \"${path:1:3}\" ${path:(-2)}"),
+            vec!["17", "x.tar.gz", "/usr/lib/x.tar", "usr", "gz"],
+        );
+        assert_eq!(expand(&mut state, "${path//\"/\"/:} ${path/#\\/usr}"), vec![":usr:lib:x.tar.gz", "/lib/x.tar.gz"]);
+        assert_eq!(expand(&mut state, "${path#$star} ${path##\"$star\"}"), vec!["/usr/lib/x.tar.gz", "/usr/lib/x.tar.gz"]);
+        assert_eq!(expand(&mut state, "\"${@^}\" ${*^^} \"${@:2}\""), vec!["One", "Two", "Three", "ONE", "TWO", "THREE", "two", "three"]);
+        assert_eq!(expand(&mut state, "\"${*%e}\" ${#*} ${#1}"), vec!["on two thre", "3", "3"]);
+        assert!(word("${path:-x}", &mut state).is_err());
+        assert!(word("${path^x}", &mut state).is_err());
+    }
+
+    #[test]
+    fn here_docs() {
+        let mut state = State::default();
+        let doc = |body: &str, expand| Here::Doc { body: body.into(), expand };
+        assert_eq!(
+            here(&doc("'$((1 + 1))' \\$((2)) \\x\\\nend\n", true), &mut state).unwrap(),
+            "'2' $((2)) \\xend\n",
+        );
+        assert_eq!(here(&doc("$((1))\n", false), &mut state).unwrap(), "$((1))\n");
+        assert_eq!(here(&Here::Str("'a  b'$((3))"), &mut state).unwrap(), "a  b3\n");
+    }
+
+    #[test]
+    fn tildes() {
+        let mut state = State {
+            pwd: PathBuf::from("/srv"),
+            ..State::default()
+        };
+        state.set_var("HOME", "/home/tester".to_string()).expect("HOME is not readonly");
+        state.set_var("OLDPWD", "/tmp".to_string()).expect("OLDPWD is not readonly");
+
+        assert_eq!(tilde("~", &state), "/home/tester");
+        assert_eq!(tilde("~/src", &state), "/home/tester/src");
+        assert_eq!(tilde("~-", &state), "/tmp");
+        assert_eq!(tilde("~+/x", &state), "/srv/x");
+        assert_eq!(tilde("a~b", &state), "a~b");
+        assert_eq!(tilde("~no-such-user-here/x", &state), "~no-such-user-here/x");
+    }
+
+    #[test]
+    fn stack() {
+        let state = State {
+            pwd: PathBuf::from("/srv"),
+            dirs: vec![PathBuf::from("/tmp"), PathBuf::from("/usr/src")],
+            ..State::default()
+        };
+        assert_eq!(tilde("~0", &state), "/srv");
+        assert_eq!(tilde("~1/x", &state), "/tmp/x");
+        assert_eq!(tilde("~+2", &state), "/usr/src");
+        assert_eq!(tilde("~-0", &state), "/usr/src");
+        assert_eq!(tilde("~-2", &state), "/srv");
+        assert_eq!(tilde("~3", &state), "~3");
+    }
+}
@@ -0,0 +1,368 @@
+//! Parameter expansion operators
+//!
+//! A braced parameter expansion may change the value of the parameter before
+//! it is used:
+//!
+//! - `${#name}` is the length of the value in characters, and `${#arr[@]}` is
+//!   the number of elements of an array
+//! - `${name#pattern}` removes the shortest prefix of the value that matches
+//!   the pattern, and `${name##pattern}` the longest
+//! - `${name%pattern}` and `${name%%pattern}` do the same for a suffix
+//! - `${name/pattern/string}` replaces the first longest match of the pattern
+//!   with the string, and `${name//pattern/string}` replaces every match;
+//!   `/#pattern` and `/%pattern` only match at the start or end of the value
+//! - `${name:offset}` and `${name:offset:length}` are the characters from an
+//!   offset, and of a length; both are arithmetic expressions. A negative
+//!   offset counts back from the end, and a negative length leaves that many
+//!   characters off the end. As `:-` is a different operator in other shells,
+//!   a negative offset must be written after a space, as `${name: -2}`.
+//! - `${name^}` and `${name^^}` turn the first character, or every character,
+//!   to upper case, and `${name,}` and `${name,,}` to lower case
+//!
+//! On `$@`, `$*`, or every element of an array, an offset and length select
+//! the fields, where `$0` comes before the positional parameters; the other
+//! operators apply to each field.
+//!
+//! Patterns are raw words, expanded as the patterns of a `case` command are,
+//! so quoted wildcards match themselves.
+
+use std::ops::Range;
+
+use crate::{
+    ast::script::is_name,
+    st::State,
+};
+
+use super::{brace, evaluate, glob, pattern, subscript, word, ExpandError, SPECIAL};
+
+/// A braced parameter expansion, `${...}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Param<'a> {
+    /// The name of the parameter: a variable, a positional parameter, or a
+    /// special parameter.
+    pub name: &'a str,
+    /// The subscript of an element of an array, `arr[subscript]`.
+    pub subscript: Option<&'a str>,
+    /// What is done to the value.
+    pub op: Option<Op<'a>>,
+}
+
+/// An operator in a parameter expansion. The patterns, strings, and
+/// expressions are raw text, to be expanded when the operator is applied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Op<'a> {
+    /// `${#name}`
+    Length,
+    /// `${name#pattern}` or `${name##pattern}` when `longest`, and the same
+    /// with `%` for a `suffix`.
+    Trim { suffix: bool, longest: bool, pattern: &'a str },
+    /// `${name/pattern/string}` and its forms.
+    Replace { place: Place, pattern: &'a str, with: &'a str },
+    /// `${name:offset:length}`
+    Slice { offset: &'a str, length: Option<&'a str> },
+    /// `${name^}` or `${name^^}` when `upper`, `${name,}` or `${name,,}`
+    /// otherwise.
+    Case { upper: bool, all: bool },
+}
+
+/// Which matches of a pattern are replaced.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Place {
+    /// `/`: the first match.
+    First,
+    /// `//`: every match.
+    All,
+    /// `/#`: a match at the start of the value.
+    Start,
+    /// `/%`: a match at the end of the value.
+    End,
+}
+
+impl<'a> Param<'a> {
+    /// Parses the body of a braced parameter expansion, between the braces.
+    pub fn parse(body: &'a str) -> Option<Self> {
+        //  `${#}` is the number of positional parameters, not a length.
+        if body.starts_with('#') && body.len() > 1 {
+            if let Some((name, subscript, "")) = name(&body[1 ..]) {
+                return Some(Param { name, subscript, op: Some(Op::Length) });
+            }
+        }
+        let (name, subscript, rest) = name(body)?;
+        let op = match rest.chars().next() {
+            None => None,
+            Some('#') | Some('%') => {
+                let longest = rest[1 ..].starts_with(&rest[.. 1]);
+                let pattern = &rest[if longest { 2 } else { 1 } ..];
+                Some(Op::Trim { suffix: rest.starts_with('%'), longest, pattern })
+            },
+            Some('/') => {
+                let (place, rest) = match rest[1 ..].chars().next() {
+                    Some('/') => (Place::All, &rest[2 ..]),
+                    Some('#') => (Place::Start, &rest[2 ..]),
+                    Some('%') => (Place::End, &rest[2 ..]),
+                    _ => (Place::First, &rest[1 ..]),
+                };
+                let (pattern, with) = split(rest, '/');
+                Some(Op::Replace { place, pattern, with: with.unwrap_or("") })
+            },
+            Some(':') => {
+                //  `:-`, `:=`, `:?`, and `:+` are not offsets.
+                if rest[1 ..].starts_with(|c| "-=?+".contains(c)) {
+                    return None;
+                }
+                let (offset, length) = split(&rest[1 ..], ':');
+                Some(Op::Slice { offset, length })
+            },
+            Some(c) if c == '^' || c == ',' => match &rest[1 ..] {
+                "" => Some(Op::Case { upper: c == '^', all: false }),
+                more if more.len() == 1 && more.starts_with(c) => {
+                    Some(Op::Case { upper: c == '^', all: true })
+                },
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(Param { name, subscript, op })
+    }
+
+    /// Tells whether the parameter stands for every positional parameter or
+    /// every element of an array, and if so, which of `@` and `*` it uses.
+    pub fn all(&self) -> Option<&'a str> {
+        match (self.name, self.subscript) {
+            (all, None) | (_, Some(all)) if all == "@" || all == "*" => Some(all),
+            _ => None,
+        }
+    }
+
+    /// Expands the parameter and applies its operator. The result has one
+    /// field for each positional parameter or element if the parameter stands
+    /// for all of them, and is one field otherwise.
+    pub fn expand(&self, state: &mut State) -> Result<Vec<String>, ExpandError> {
+        let values = match (self.name, self.subscript) {
+            ("@", None) | ("*", None) => state.args.clone(),
+            (name, Some("@")) | (name, Some("*")) => state.elements(name),
+            (name, Some(sub)) => {
+                let sub = subscript(name, sub, state)?;
+                let value = state.value(name).and_then(|value| value.element(&sub));
+                vec![value.unwrap_or_default().to_string()]
+            },
+            (name, None) => vec![state.param(name).unwrap_or_default()],
+        };
+        let op = match self.op {
+            Some(ref op) => op,
+            None => return Ok(values),
+        };
+        Ok(match op {
+            Op::Length if self.all().is_some() => vec![values.len().to_string()],
+            Op::Length => values.iter().map(|value| value.chars().count().to_string()).collect(),
+            Op::Slice { offset, length } => {
+                let offset = evaluate(offset, state)?;
+                let length = match length {
+                    Some(length) => Some(evaluate(length, state)?),
+                    None => None,
+                };
+                match self.all() {
+                    Some(_) if self.subscript.is_none() => {
+                        let fields = Some(state.name.clone()).into_iter().chain(values).collect::<Vec<_>>();
+                        fields[range(fields.len(), offset, length)].to_vec()
+                    },
+                    Some(_) => values[range(values.len(), offset, length)].to_vec(),
+                    None => values.iter().map(|value| {
+                        let chars = value.chars().collect::<Vec<_>>();
+                        chars[range(chars.len(), offset, length)].iter().collect()
+                    }).collect(),
+                }
+            },
+            Op::Trim { suffix, longest, pattern: pat } => {
+                let pat = pattern(pat, state)?;
+                values.iter().map(|value| trim(value, &pat, *suffix, *longest).to_string()).collect()
+            },
+            Op::Replace { place, pattern: pat, with } => {
+                let pat = pattern(pat, state)?;
+                let with = word(with, state)?;
+                values.iter().map(|value| replace(value, &pat, &with, *place)).collect()
+            },
+            Op::Case { upper, all } => {
+                values.iter().map(|value| case(value, *upper, *all)).collect()
+            },
+        })
+    }
+}
+
+/// Splits the name of a parameter, and the subscript after it if there is
+/// one, from the start of the body of an expansion.
+fn name(body: &str) -> Option<(&str, Option<&str>, &str)> {
+    let end = match body.chars().next()? {
+        //  In braces, a positional parameter may have several digits.
+        c if c.is_ascii_digit() => {
+            body.find(|c: char| !c.is_ascii_digit()).unwrap_or(body.len())
+        },
+        c if SPECIAL.contains(c) => 1,
+        _ => body.find(|c: char| !(c == '_' || c.is_ascii_alphanumeric())).unwrap_or(body.len()),
+    };
+    let (name, rest) = body.split_at(end);
+    if !is_name(name) {
+        return Some((name, None, rest)).filter(|_| end > 0);
+    }
+    if !rest.starts_with('[') {
+        return Some((name, None, rest));
+    }
+    let close = rest.find(']')?;
+    Some((name, Some(&rest[1 .. close]), &rest[close + 1 ..]))
+}
+
+/// Splits text at the first `sep` that is not quoted, escaped, or in a nested
+/// expansion.
+fn split(text: &str, sep: char) -> (&str, Option<&str>) {
+    let mut at = None;
+    brace::scan(text, |i, c| {
+        if c == sep {
+            at = Some(i);
+        }
+        at.is_none()
+    });
+    match at {
+        Some(i) => (&text[.. i], Some(&text[i + sep.len_utf8() ..])),
+        None => (text, None),
+    }
+}
+
+/// Works out the range of the items selected by an offset and a length.
+fn range(len: usize, offset: i64, length: Option<i64>) -> Range<usize> {
+    let len = len as i64;
+    let start = if offset < 0 { len + offset } else { offset };
+    if start < 0 || start > len {
+        return 0 .. 0;
+    }
+    let end = match length {
+        Some(length) if length < 0 => len + length,
+        Some(length) => start.saturating_add(length).min(len),
+        None => len,
+    };
+    if end < start {
+        return 0 .. 0;
+    }
+    start as usize .. end as usize
+}
+
+/// The byte offsets at which the characters of a text begin, and its length.
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices().map(|(i, _)| i).chain(Some(text.len())).collect()
+}
+
+/// Removes the shortest or longest prefix or suffix of a value that matches a
+/// pattern.
+fn trim<'a>(value: &'a str, pattern: &str, suffix: bool, longest: bool) -> &'a str {
+    let mut ends = boundaries(value);
+    //  The candidates are tried from the shortest part to the longest.
+    if suffix {
+        ends.reverse();
+    }
+    if longest {
+        ends.reverse();
+    }
+    for i in ends {
+        if !suffix && glob::matches(pattern, &value[.. i]) {
+            return &value[i ..];
+        }
+        if suffix && glob::matches(pattern, &value[i ..]) {
+            return &value[.. i];
+        }
+    }
+    value
+}
+
+/// Replaces the longest matches of a pattern in a value.
+fn replace(value: &str, pattern: &str, with: &str, place: Place) -> String {
+    let ends = boundaries(value);
+    match place {
+        Place::Start => {
+            return match ends.iter().rev().find(|&&i| glob::matches(pattern, &value[.. i])) {
+                Some(&i) => format!("{}{}", with, &value[i ..]),
+                None => value.to_string(),
+            };
+        },
+        Place::End => {
+            return match ends.iter().find(|&&i| glob::matches(pattern, &value[i ..])) {
+                Some(&i) => format!("{}{}", &value[.. i], with),
+                None => value.to_string(),
+            };
+        },
+        Place::First | Place::All => {},
+    }
+    if pattern.is_empty() {
+        return value.to_string();
+    }
+    let mut out = String::with_capacity(value.len());
+    //  The value up to `done` has been copied or replaced.
+    let mut done = 0;
+    let mut replaced = false;
+    for (n, &start) in ends.iter().enumerate() {
+        if start < done || (replaced && place == Place::First) {
+            continue;
+        }
+        let end = ends[n + 1 ..].iter().rev().find(|&&end| glob::matches(pattern, &value[start .. end]));
+        if let Some(&end) = end {
+            out.push_str(&value[done .. start]);
+            out.push_str(with);
+            done = end;
+            replaced = true;
+        }
+    }
+    out.push_str(&value[done ..]);
+    out
+}
+
+/// Changes the case of the first character of a value, or of all of them.
+fn case(value: &str, upper: bool, all: bool) -> String {
+    let change = |c: char| -> String {
+        if upper { c.to_uppercase().collect() } else { c.to_lowercase().collect() }
+    };
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) if !all => change(first) + chars.as_str(),
+        _ => value.chars().map(change).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let param = |body| Param::parse(body).expect("the expansion is valid");
+        assert_eq!(param("#x"), Param { name: "x", subscript: None, op: Some(Op::Length) });
+        assert_eq!(param("#").name, "#");
+        assert_eq!(param("a[$i]##*/").op, Some(Op::Trim { suffix: false, longest: true, pattern: "*/" }));
+        assert_eq!(param("a[$i]##*/").subscript, Some("$i"));
+        assert_eq!(param("10%.*").op, Some(Op::Trim { suffix: true, longest: false, pattern: ".*" }));
+        assert_eq!(
+            param("x//\"/\"/${y/a/b}").op,
+            Some(Op::Replace { place: Place::All, pattern: "\"/\"", with: "${y/a/b}" }),
+        );
+        assert_eq!(param("@: -2").op, Some(Op::Slice { offset: " -2", length: None }));
+        assert_eq!(param("x:1:$n").op, Some(Op::Slice { offset: "1", length: Some("$n") }));
+        assert_eq!(param("x,,").op, Some(Op::Case { upper: false, all: true }));
+        for bad in &["x:-y", "x^^^", "x-y", "", "a b"] {
+            assert_eq!(Param::parse(bad), None);
+        }
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(trim("a/b/c", "*/", false, false), "b/c");
+        assert_eq!(trim("a/b/c", "*/", false, true), "c");
+        assert_eq!(trim("x.tar.gz", ".*", true, false), "x.tar");
+        assert_eq!(trim("x.tar.gz", ".*", true, true), "x");
+        assert_eq!(replace("banana", "an", "AN", Place::First), "bANana");
+        assert_eq!(replace("banana", "a*n", "-", Place::All), "b-a");
+        assert_eq!(replace("banana", "b", "", Place::End), "banana");
+        assert_eq!(replace("banana", "", "<", Place::Start), "<banana");
+        assert_eq!(case("élan vital", true, false), "Élan vital");
+        assert_eq!(case("MiXeD", false, true), "mixed");
+        assert_eq!(range(5, -2, None), 3 .. 5);
+        assert_eq!(range(5, 1, Some(-1)), 1 .. 4);
+        assert_eq!(range(5, 9, Some(1)), 0 .. 0);
+    }
+}
@@ -0,0 +1,217 @@
+//! Process substitution
+//!
+//! `<(command)` starts the command with its output going into a pipe, and is
+//! replaced by a path, such as `/dev/fd/63`, from which the pipe can be read.
+//! `>(command)` is the reverse: whatever is written to the path is the input of
+//! the command. Either way, the command runs alongside the command it is
+//! substituted into, which only sees a file name. What the command prints is
+//! collected, and only shown once it has finished, after the command it was
+//! substituted into.
+//!
+//! The substituted command is a program and its arguments, expanded like any
+//! other words, and may hold process substitutions of its own.
+
+use std::{borrow::Cow, fs::File, io};
+
+use duct::{cmd, Handle};
+
+use super::ExpandError;
+use crate::{ast::Words, st::State, token};
+
+/// A command started for a process substitution.
+pub struct Substitution {
+    handle: Handle,
+    /// The end of the pipe that belongs to the command being substituted into.
+    /// The shell keeps it open until that command is done.
+    pipe: File,
+}
+
+/// Replaces each process substitution in a command line with the path of its
+/// pipe, and starts its command.
+///
+/// Process substitutions are found outside of quotes, but not inside double
+/// quotes or meta-sequences, or after a backslash.
+pub fn substitute<'a>(
+    line: &'a str,
+    state: &mut State,
+) -> Result<(Cow<'a, str>, Vec<Substitution>), ExpandError> {
+    let mut found = Vec::new();
+    let mut out = String::new();
+    //  The text up to `done` has been copied to `out`.
+    let mut done = 0;
+    let mut rem = line;
+    while let Some(c) = rem.chars().next() {
+        let at = line.len() - rem.len();
+        rem = match c {
+            '\\' => {
+                let mut chars = rem.chars();
+                chars.next();
+                chars.next();
+                chars.as_str()
+            },
+            '\'' => token::squote(rem).map(|(r, _)| r).unwrap_or(""),
+            '"' => token::dquote(rem).map(|(r, _)| r).unwrap_or(""),
+            '$' if rem[1 ..].starts_with('(') || rem[1 ..].starts_with('{') => {
+                token::shell_meta(rem).map(|(r, _)| r).unwrap_or("")
+            },
+            //  `<<(` is a here-document operator and a word, not a
+            //  substitution.
+            '<' if rem.starts_with("<<") => rem.trim_start_matches('<'),
+            '<' | '>' => match token::process(rem) {
+                Ok((rest, (op, command))) => {
+                    let sub = start(op, command, state, &mut found)?;
+                    out.push_str(&line[done .. at]);
+                    out.push_str(&path(&sub.pipe));
+                    found.push(sub);
+                    done = line.len() - rest.len();
+                    rest
+                },
+                Err(_) => &rem[1 ..],
+            },
+            c => &rem[c.len_utf8() ..],
+        };
+    }
+    for sub in &found {
+        inherit(&sub.pipe)?;
+    }
+    if done == 0 {
+        return Ok((Cow::Borrowed(line), found));
+    }
+    out.push_str(&line[done ..]);
+    Ok((Cow::Owned(out), found))
+}
+
+/// Waits for the commands of process substitutions to finish, once the command
+/// they were substituted into is done, and collects what they printed: the
+/// output of each `>(command)`, and the errors of every command.
+///
+/// Every pipe is closed before any command is waited for, so that each command
+/// sees the end of its input, or stops writing output that nobody reads.
+pub fn finish(found: Vec<Substitution>) -> io::Result<Vec<u8>> {
+    let handles = found.into_iter().map(|sub| sub.handle).collect::<Vec<_>>();
+    let mut printed = Vec::new();
+    for handle in handles {
+        let output = handle.wait()?;
+        printed.extend_from_slice(&output.stdout);
+        printed.extend_from_slice(&output.stderr);
+    }
+    Ok(printed)
+}
+
+/// Starts the command of a process substitution, after the substitutions
+/// nested in it, which are added to `found`.
+fn start(
+    op: &str,
+    command: &str,
+    state: &mut State,
+    found: &mut Vec<Substitution>,
+) -> Result<Substitution, ExpandError> {
+    let (command, nested) = substitute(command, state)?;
+    found.extend(nested);
+    let words = super::words(Words::new(&command), state)?;
+    let (program, args) = match words.split_first() {
+        Some(split) => split,
+        None => return Err(ExpandError::BadSubstitution(format!("{}({})", op, command))),
+    };
+    let failed = |e: io::Error| match e.kind() {
+        io::ErrorKind::NotFound => ExpandError::Process(format!("command not found: {}", program)),
+        _ => ExpandError::Process(e.to_string()),
+    };
+    let (read, write) = pipe().map_err(failed)?;
+    let job = cmd(program, args)
+        .full_env(state.environment())
+        .unchecked()
+        .stderr_capture();
+    let (job, pipe) = if op == "<" {
+        (job.stdout_file(write), read)
+    } else {
+        (job.stdin_file(read).stdout_capture(), write)
+    };
+    let handle = job.start().map_err(failed)?;
+    Ok(Substitution { handle, pipe })
+}
+
+/// Opens a pipe. Neither end is inherited by the programs the shell starts,
+/// until `inherit` allows it.
+#[cfg(unix)]
+fn pipe() -> io::Result<(File, File)> {
+    use std::os::unix::io::FromRawFd;
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for fd in &fds {
+        if unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((read, write))
+}
+
+#[cfg(windows)]
+fn pipe() -> io::Result<(File, File)> {
+    Err(io::Error::new(io::ErrorKind::Other, "process substitution is not supported"))
+}
+
+/// Lets the programs the shell starts inherit one end of a pipe, which is how
+/// they reach it through its path.
+#[cfg(unix)]
+fn inherit(pipe: &File) -> Result<(), ExpandError> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::fcntl(pipe.as_raw_fd(), libc::F_SETFD, 0) } != 0 {
+        return Err(ExpandError::Process(io::Error::last_os_error().to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn inherit(_: &File) -> Result<(), ExpandError> {
+    Ok(())
+}
+
+/// The path through which a program reaches an end of a pipe that it inherits.
+#[cfg(unix)]
+fn path(pipe: &File) -> String {
+    use std::os::unix::io::AsRawFd;
+    format!("/dev/fd/{}", pipe.as_raw_fd())
+}
+
+#[cfg(windows)]
+fn path(_: &File) -> String {
+    unreachable!("pipes are never opened on windows")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitution() {
+        let mut state = State::default();
+        let (line, found) = substitute("cat <(echo 'a )') '<(x)' \\<(y) <<(z)", &mut state)
+            .expect("echo runs");
+        assert_eq!(found.len(), 1);
+        let expected = format!("cat {} '<(x)' \\<(y) <<(z)", path(&found[0].pipe));
+        assert_eq!(line, expected);
+
+        let fd = found[0].pipe.try_clone().expect("the pipe is open");
+        let mut read = String::new();
+        io::Read::read_to_string(&mut &fd, &mut read).expect("the pipe is readable");
+        drop(fd);
+        assert_eq!(read, "a )\n");
+        assert!(finish(found).expect("echo finishes").is_empty());
+
+        assert!(substitute("cat <(no-such-program-here)", &mut state).is_err());
+
+        //  Both the output and the errors of the command are shown.
+        let (_, found) = substitute("tee >(sh -c 'cat; echo err >&2')", &mut state)
+            .expect("sh runs");
+        let printed = finish(found).expect("sh finishes");
+        assert_eq!(String::from_utf8_lossy(&printed), "err\n");
+        let (_, found) = substitute("x >(sh -c 'echo out; echo err >&2')", &mut state)
+            .expect("sh runs");
+        let printed = finish(found).expect("sh finishes");
+        assert_eq!(String::from_utf8_lossy(&printed), "out\nerr\n");
+    }
+}
@@ -0,0 +1,233 @@
+//! Frecency-ranked directory history, for the `z` builtin.
+//!
+//! Every directory the shell enters is recorded with a visit count and the time
+//! of the last visit. `z` picks the best-ranked directory whose path matches
+//! the fragments it is given, so that `z src ysh` can jump to
+//! `/home/user/src/ysh` from anywhere.
+//!
+//! The score of a directory combines how often and how recently it was visited:
+//! the visit count is multiplied by 4 within the last hour, by 2 within the
+//! last day, by 1/2 within the last week, and by 1/4 after that. To keep old
+//! favorites from crowding out new ones forever, all counts decay by 1% once
+//! their total passes a limit, and entries that decay below one visit are
+//! forgotten.
+//!
+//! The history is stored one directory per line as `path|count|time` in
+//! `$YSH_Z_DATA`, or `~/.ysh_z` when that is not set.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The total visit count at which all counts start to decay.
+const AGING_LIMIT: f64 = 9000.0;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// The recorded visits to directories.
+#[derive(Clone, Debug, Default)]
+pub struct Frecency {
+    /// The file the history is kept in, if any.
+    file: Option<PathBuf>,
+    entries: HashMap<PathBuf, Visits>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Visits {
+    count: f64,
+    last: u64,
+}
+
+// ===== impl Frecency =====
+
+impl Frecency {
+    /// Loads the history from `file`. A missing or unreadable file is an empty
+    /// history, and malformed lines are skipped.
+    pub fn load(file: PathBuf) -> Self {
+        let entries = fs::read_to_string(&file)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.rsplitn(3, '|');
+                let last = parts.next()?.parse().ok()?;
+                let count = parts.next()?.parse().ok()?;
+                let path = PathBuf::from(parts.next()?);
+                Some((path, Visits { count, last }))
+            })
+            .collect();
+        Self { file: Some(file), entries }
+    }
+
+    /// Gets the location of the history file: `data`, the value of
+    /// `$YSH_Z_DATA`, unless it is unset or empty.
+    pub fn default_file(data: Option<String>, home: Option<&Path>) -> Option<PathBuf> {
+        data.filter(|f| !f.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home.map(|h| h.join(".ysh_z")))
+    }
+
+    /// Records a visit to `dir`, and saves the history.
+    ///
+    /// Saving is best-effort: losing a visit is not worth interrupting `cd`.
+    pub fn visit(&mut self, dir: &Path) {
+        self.record(dir, now());
+        if let Some(ref file) = self.file {
+            let text = self.entries.iter()
+                .map(|(path, v)| format!("{}|{}|{}\n", path.display(), v.count, v.last))
+                .collect::<String>();
+            drop(fs::write(file, text));
+        }
+    }
+
+    fn record(&mut self, dir: &Path, now: u64) {
+        let visits = self.entries.entry(dir.to_path_buf())
+            .or_insert(Visits { count: 0.0, last: now });
+        visits.count += 1.0;
+        visits.last = now;
+
+        let total: f64 = self.entries.values().map(|v| v.count).sum();
+        if total > AGING_LIMIT {
+            for visits in self.entries.values_mut() {
+                visits.count *= 0.99;
+            }
+            self.entries.retain(|_, v| v.count >= 1.0);
+        }
+    }
+
+    /// Finds the directories matching all of `fragments`, best first.
+    ///
+    /// Directories that no longer exist are skipped.
+    pub fn matches(&self, fragments: &[&str]) -> Vec<&Path> {
+        self.ranked(fragments, now())
+            .into_iter()
+            .filter(|dir| dir.is_dir())
+            .collect()
+    }
+
+    fn ranked(&self, fragments: &[&str], now: u64) -> Vec<&Path> {
+        let mut found = self.entries.iter()
+            .filter(|(path, _)| matches(path, fragments))
+            .map(|(path, visits)| (path.as_path(), visits.score(now)))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        found.into_iter().map(|(path, _)| path).collect()
+    }
+}
+
+// ===== impl Visits =====
+
+impl Visits {
+    fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last);
+        self.count * if age < HOUR {
+            4.0
+        } else if age < DAY {
+            2.0
+        } else if age < WEEK {
+            0.5
+        } else {
+            0.25
+        }
+    }
+}
+
+/// Checks that the fragments all occur in `path`, in order.
+///
+/// Fragments without capital letters match case-insensitively. The last
+/// fragment must match within the final component of the path, so that
+/// `z src` prefers `~/src` over everything underneath it.
+fn matches(path: &Path, fragments: &[&str]) -> bool {
+    //  Characters are compared one at a time, so that folding the case of one
+    //  cannot move the position in the path.
+    let text = path.to_string_lossy().chars().collect::<Vec<_>>();
+    let mut pos = 0;
+    for (n, frag) in fragments.iter().enumerate() {
+        let fold = !frag.chars().any(char::is_uppercase);
+        let same = |a: char, b: char| a == b || (fold && a.to_lowercase().eq(b.to_lowercase()));
+        let needle = frag.chars().collect::<Vec<_>>();
+        let found = (pos ..= text.len())
+            .take_while(|at| at + needle.len() <= text.len())
+            .find(|&at| text[at ..].iter().zip(&needle).all(|(&a, &b)| same(a, b)));
+        match found {
+            Some(at) => pos = at + needle.len(),
+            None => return false,
+        }
+        if n == fragments.len() - 1 && text[pos ..].contains(&'/') {
+            return false;
+        }
+    }
+    true
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching() {
+        let path = Path::new("/home/ferris/src/ysh");
+        assert!(matches(path, &["ysh"]));
+        assert!(matches(path, &["src", "ysh"]));
+        assert!(!matches(path, &["SH"]));
+        assert!(matches(path, &["ys"]));
+        assert!(!matches(path, &["ysh", "src"]));
+        assert!(!matches(path, &["src"]));
+        assert!(!matches(path, &["Ferris", "ysh"]));
+        //  Folding the case of `İ` makes it longer, which must not throw the
+        //  position in the path off.
+        let path = Path::new("/İİx/Foo/bar");
+        assert!(matches(path, &["x", "Foo", "bar"]));
+        assert!(matches(path, &["İİ", "foo", "ar"]));
+        assert!(!matches(path, &["foo", "x"]));
+    }
+
+    #[test]
+    fn data_file() {
+        let home = Path::new("/home/ferris");
+        let file = |data: Option<&str>| Frecency::default_file(data.map(String::from), Some(home));
+        assert_eq!(file(Some("/tmp/z")), Some(PathBuf::from("/tmp/z")));
+        assert_eq!(file(Some("")), Some(home.join(".ysh_z")));
+        assert_eq!(file(None), Some(home.join(".ysh_z")));
+    }
+
+    #[test]
+    fn ranking() {
+        let mut z = Frecency::default();
+        let now = 10 * WEEK;
+        //  Visited often, but long ago
+        for _ in 0 .. 10 {
+            z.record(Path::new("/old/proj"), now - 2 * WEEK);
+        }
+        //  Visited a few times just now
+        for _ in 0 .. 3 {
+            z.record(Path::new("/new/proj"), now);
+        }
+        assert_eq!(z.ranked(&["proj"], now), vec![
+            Path::new("/new/proj"),
+            Path::new("/old/proj"),
+        ]);
+    }
+
+    #[test]
+    fn aging() {
+        let mut z = Frecency::default();
+        z.record(Path::new("/rare"), 0);
+        for _ in 0 .. AGING_LIMIT as usize {
+            z.record(Path::new("/common"), 0);
+        }
+        assert!(z.entries.get(Path::new("/rare")).is_none());
+        assert!(z.entries[Path::new("/common")].count < AGING_LIMIT);
+    }
+}
@@ -0,0 +1,183 @@
+//! Decoding of raw terminal input into key events.
+//!
+//! In raw mode the terminal hands the shell one character at a time. Most keys
+//! are a single character, but the arrow keys, Home/End, Delete, and anything
+//! pressed with Alt arrive as multi-character escape sequences that begin with
+//! ESC (U+001B). This module folds those sequences back into a single `Key` so
+//! that the read loop never mistakes the start of an arrow key for a bare ESC.
+
+/// A single key press, as decoded from terminal input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Key {
+    /// A printable character.
+    Char(char),
+    /// A character pressed while holding Ctrl, such as `Ctrl('c')`.
+    Ctrl(char),
+    /// A character pressed while holding Alt (sent as ESC + the character).
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// A lone ESC with nothing following it.
+    Esc,
+    /// An escape sequence that was well-formed but is not recognized.
+    Unknown,
+}
+
+impl Key {
+    /// Decodes one key, given its first character.
+    ///
+    /// `next` is called to fetch any further characters of an escape sequence.
+    /// It must return `None` when no more input is immediately available, so
+    /// that a lone ESC press can be told apart from the start of a sequence.
+    pub fn read(first: char, mut next: impl FnMut() -> Option<char>) -> Self {
+        match first {
+            '\u{001B}' => match next() {
+                None => Key::Esc,
+                //  Control Sequence Introducer: `ESC [ params final`
+                Some('[') => csi(&mut next),
+                //  Single Shift Three: `ESC O final`, sent by some terminals
+                //  for the arrow and Home/End keys in application mode.
+                Some('O') => match next() {
+                    Some(c) => Key::ss3(c),
+                    None => Key::Alt('O'),
+                },
+                Some('\u{001B}') => Key::Esc,
+                Some(c) => Key::Alt(c),
+            },
+            '\r' | '\n' => Key::Enter,
+            '\t' => Key::Tab,
+            //  Backspace and Delete because on *nix it can send either or to
+            //  mean the same thing
+            '\u{0008}' | '\u{007F}' => Key::Backspace,
+            //  The remaining C0 control characters are Ctrl + a letter.
+            c @ '\u{0001}' ..= '\u{001A}' => {
+                Key::Ctrl((b'a' + c as u8 - 1) as char)
+            },
+            c if c.is_control() => Key::Unknown,
+            c => Key::Char(c),
+        }
+    }
+
+    /// Decodes the final character of an SS3 sequence.
+    fn ss3(c: char) -> Self {
+        match c {
+            'A' => Key::Up,
+            'B' => Key::Down,
+            'C' => Key::Right,
+            'D' => Key::Left,
+            'H' => Key::Home,
+            'F' => Key::End,
+            _ => Key::Unknown,
+        }
+    }
+}
+
+/// Decodes the remainder of a CSI sequence, after `ESC [`.
+///
+/// CSI sequences are zero or more parameter and intermediate bytes (`0x20` to
+/// `0x3F`) followed by a single final byte (`0x40` to `0x7E`). Modifier
+/// parameters (as in `ESC [ 1 ; 5 C` for Ctrl-Right) are accepted and
+/// discarded. A sequence with any other parameter bytes, such as the private
+/// `ESC [ ? 1 ; 2 c` or a mouse report, is read whole and is not a key.
+fn csi(next: &mut impl FnMut() -> Option<char>) -> Key {
+    let mut params = String::new();
+    let mut private = false;
+    loop {
+        match next() {
+            Some(c) if c.is_ascii_digit() || c == ';' => params.push(c),
+            Some(c @ '\u{20}' ..= '\u{3F}') => {
+                params.push(c);
+                private = true;
+            },
+            Some('\u{40}' ..= '\u{7E}') if private => return Key::Unknown,
+            Some('~') => {
+                let code = params.split(';').next().unwrap_or("");
+                return match code {
+                    "1" | "7" => Key::Home,
+                    "2" => Key::Insert,
+                    "3" => Key::Delete,
+                    "4" | "8" => Key::End,
+                    "5" => Key::PageUp,
+                    "6" => Key::PageDown,
+                    _ => Key::Unknown,
+                };
+            },
+            Some(c @ '\u{40}' ..= '\u{7E}') => return Key::ss3(c),
+            //  Anything else cuts the sequence off, as does running out of
+            //  input; there is nothing sensible to report.
+            Some(_) | None => return Key::Unknown,
+        }
+    }
+}
+
+/// Reports whether more input is immediately available on stdin.
+///
+/// Terminals send escape sequences in a single write, so the bytes after an
+/// ESC are already waiting when the ESC is read. A short wait distinguishes
+/// that case from the user pressing ESC on its own.
+#[cfg(unix)]
+pub fn pending() -> bool {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fd, 1, 25) > 0 }
+}
+
+#[cfg(windows)]
+pub fn pending() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(text: &str) -> Key {
+        let mut chars = text.chars();
+        let first = chars.next().expect("test input is not empty");
+        Key::read(first, || chars.next())
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(decode("a"), Key::Char('a'));
+        assert_eq!(decode("\r"), Key::Enter);
+        assert_eq!(decode("\u{7F}"), Key::Backspace);
+        assert_eq!(decode("\u{3}"), Key::Ctrl('c'));
+        assert_eq!(decode("\u{4}"), Key::Ctrl('d'));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(decode("\u{1B}"), Key::Esc);
+        assert_eq!(decode("\u{1B}[A"), Key::Up);
+        assert_eq!(decode("\u{1B}[D"), Key::Left);
+        assert_eq!(decode("\u{1B}OH"), Key::Home);
+        assert_eq!(decode("\u{1B}[3~"), Key::Delete);
+        assert_eq!(decode("\u{1B}[1;5C"), Key::Right);
+        assert_eq!(decode("\u{1B}b"), Key::Alt('b'));
+    }
+
+    #[test]
+    fn private_sequences() {
+        for text in &["\u{1B}[?1;2cx", "\u{1B}[<0;12;7Mx", "\u{1B}[ qx"] {
+            let mut chars = text.chars().skip(1);
+            assert_eq!(Key::read('\u{1B}', || chars.next()), Key::Unknown);
+            //  Nothing of the sequence is left over to be taken as typing.
+            assert_eq!(chars.collect::<String>(), "x");
+        }
+    }
+}
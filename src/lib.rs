@@ -2,10 +2,15 @@
 
 #![feature(crate_visibility_modifier)]
 
+pub mod arith;
 pub mod ast;
-pub mod env;
+pub mod exec;
+pub mod expand;
+pub mod frecency;
+pub mod key;
 pub mod line;
 pub mod parse;
+pub mod prompt;
 pub mod st;
 pub mod term;
 pub mod token;
@@ -1,21 +1,113 @@
 #![feature(crate_visibility_modifier)]
 
-use std::process::exit;
+use std::{
+    env,
+    fs,
+    process::exit,
+};
 
 //  main.rs is a different crate than lib.rs, so the `crate` keyword in lib
 //  is the name `ysh` here.
-use ysh::st::State;
+use ysh::{st::State, term};
 
 use crossterm::Screen;
 
+/// How the shell was asked to run.
+///
+/// ```text
+/// ysh [-l]
+/// ysh [-l] -c command [name [argument...]]
+/// ysh [-l] file [argument...]
+/// ```
+///
+/// `-l` (or `--login`), or a name starting with `-` as `login` gives it, makes
+/// a login shell, which runs the profile as it starts.
+///
+/// A script that begins with a `#!` line naming `ysh` is run as `ysh file`,
+/// and the `#!` line is a comment to the shell. Without a command or a file,
+/// the shell is interactive when its input is a terminal, and otherwise reads
+/// commands from its input without prompting.
+enum Mode {
+    Interactive,
+    Stdin,
+    Command(String),
+    File(String),
+}
+
 fn main() {
-    // Put it in raw mode
-    let mut screen = Screen::new(true);
-    State::default()
-        .init(&mut screen)
-        .and_then(move |mut state| state.run(screen))
-        .unwrap_or_else(|e| {
-            eprintln!("{}", e);
-            exit(1);
-        });
+    let mut args = env::args();
+    let shell = args.next().unwrap_or_else(|| "ysh".to_string());
+    let mut args = args.collect::<Vec<_>>();
+    let mut login = shell.starts_with('-');
+    while !args.is_empty() && (args[0] == "-l" || args[0] == "--login") {
+        args.remove(0);
+        login = true;
+    }
+    let mode = if args.is_empty() && term::stdin_is_tty() {
+        Mode::Interactive
+    } else if args.is_empty() {
+        Mode::Stdin
+    } else if args[0] == "-c" {
+        if args.len() < 2 {
+            eprintln!("ysh: -c: option requires an argument");
+            exit(2);
+        }
+        let command = args.remove(1);
+        args.remove(0);
+        Mode::Command(command)
+    } else {
+        Mode::File(args.remove(0))
+    };
+    //  The name of the shell, `$0`, is the script it runs, or for `-c`, the
+    //  first argument after the command.
+    let name = match mode {
+        Mode::Interactive | Mode::Stdin => shell,
+        Mode::Command(_) if !args.is_empty() => args.remove(0),
+        Mode::Command(_) => shell,
+        Mode::File(ref file) => file.clone(),
+    };
+    let mut state = State::default();
+    state.name = name;
+    state.args = args;
+    state.login = login;
+
+    let result = match mode {
+        Mode::Interactive => {
+            // Put it in raw mode
+            let mut screen = Screen::new(true);
+            state.interactive = true;
+            state.init(&mut screen)
+                .and_then(move |mut state| state.run(screen))
+                .map(|()| 0)
+        },
+        Mode::Stdin => {
+            let mut screen = Screen::new(false);
+            state.init(&mut screen)
+                .and_then(|mut state| {
+                    state.run_input(term::Unbuffered, &mut screen)?;
+                    Ok(state.status)
+                })
+        },
+        Mode::Command(text) => script(state, &text),
+        Mode::File(file) => match fs::read_to_string(&file) {
+            Ok(text) => script(state, &text),
+            Err(e) => {
+                eprintln!("ysh: {}: {}", file, e);
+                exit(127);
+            },
+        },
+    };
+    let status = result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    exit(status);
+}
+
+/// Runs a script, and gives the status the shell exits with.
+fn script(state: State, text: &str) -> Result<i32, failure::Error> {
+    let mut screen = Screen::new(false);
+    let mut state = state.init(&mut screen)?;
+    state.script(text, &mut screen)?;
+    Ok(state.status)
 }
@@ -0,0 +1,230 @@
+//! The git prompt segment.
+//!
+//! The branch is found by reading the repository's `HEAD` file directly, which
+//! is cheap enough to do for every prompt. The working tree status is not: on
+//! a large repository `git status` can take seconds. It is therefore computed
+//! on a background thread, and the prompt waits for it only up to a small time
+//! budget. If the status is not ready in time, the prompt shows the last known
+//! status for that repository, and the fresh one is picked up next time.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use duct::cmd;
+use lazy_static::lazy_static;
+
+/// How long a prompt may wait for `git status` before drawing without it.
+const BUDGET: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    /// The most recent status seen for each work tree.
+    static ref CACHE: Mutex<HashMap<PathBuf, Status>> = Mutex::new(HashMap::new());
+    /// Work trees that have a status refresh running in the background.
+    static ref RUNNING: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+/// A git repository, as located from some directory inside it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Repo {
+    /// The `.git` directory holding the repository data.
+    pub git_dir: PathBuf,
+    /// The top-level directory of the checkout.
+    pub work_tree: PathBuf,
+}
+
+/// What `HEAD` points at.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Head {
+    Branch(String),
+    /// A detached `HEAD`, shown as an abbreviated commit hash.
+    Detached(String),
+}
+
+/// The state of the working tree relative to `HEAD` and its upstream.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Status {
+    pub staged: bool,
+    pub dirty: bool,
+    pub untracked: bool,
+    pub ahead: u32,
+    pub behind: u32,
+}
+
+// ===== impl Repo =====
+
+impl Repo {
+    /// Finds the repository containing `dir`, by walking up to the nearest
+    /// `.git` entry.
+    ///
+    /// `.git` is usually a directory, but in linked work trees and submodules
+    /// it is a file of the form `gitdir: <path>` pointing elsewhere.
+    pub fn discover(dir: &Path) -> Option<Self> {
+        dir.ancestors().find_map(|work_tree| {
+            let dotgit = work_tree.join(".git");
+            let git_dir = if dotgit.is_dir() {
+                dotgit
+            } else {
+                let link = fs::read_to_string(&dotgit).ok()?;
+                work_tree.join(link.trim().trim_start_matches("gitdir:").trim())
+            };
+            Some(Self { git_dir, work_tree: work_tree.to_path_buf() })
+        })
+    }
+
+    /// Reads `HEAD` to find the current branch or commit.
+    pub fn head(&self) -> Option<Head> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        let head = head.trim();
+        Some(if head.starts_with("ref:") {
+            let name = head["ref:".len() ..].trim();
+            Head::Branch(name.trim_start_matches("refs/heads/").to_string())
+        } else {
+            Head::Detached(head.chars().take(7).collect())
+        })
+    }
+
+    /// Gets the working tree status, waiting no longer than the time budget.
+    ///
+    /// At most one refresh runs per work tree at a time. When a refresh is
+    /// already running, or does not finish within the budget, the last cached
+    /// status is returned instead, which may be `None` on a first visit.
+    pub fn status(&self) -> Option<Status> {
+        let (tx, rx) = mpsc::channel();
+        let started = RUNNING.lock().ok()?.insert(self.work_tree.clone());
+        if started {
+            let tree = self.work_tree.clone();
+            thread::spawn(move || {
+                let status = Status::read(&tree);
+                if let (Some(status), Ok(mut cache)) = (status, CACHE.lock()) {
+                    cache.insert(tree.clone(), status);
+                }
+                if let Ok(mut running) = RUNNING.lock() {
+                    running.remove(&tree);
+                }
+                //  The prompt may have stopped waiting already.
+                drop(tx.send(status));
+            });
+        } else {
+            drop(tx);
+        }
+        rx.recv_timeout(BUDGET).ok()
+            .and_then(|status| status)
+            .or_else(|| CACHE.lock().ok()?.get(&self.work_tree).cloned())
+    }
+}
+
+// ===== impl Status =====
+
+impl Status {
+    /// Runs `git status` in a work tree and summarizes its output.
+    fn read(tree: &Path) -> Option<Self> {
+        cmd("git", &["status", "--porcelain=v2", "--branch"])
+            .dir(tree)
+            //  Don't take the index lock just to refresh it, so that the
+            //  prompt never gets in the way of the user's own git commands.
+            .env("GIT_OPTIONAL_LOCKS", "0")
+            .stderr_null()
+            .read()
+            .ok()
+            .map(|out| Self::parse(&out))
+    }
+
+    /// Parses the output of `git status --porcelain=v2 --branch`.
+    fn parse(text: &str) -> Self {
+        let mut status = Self::default();
+        for line in text.lines() {
+            let mut fields = line.split(' ');
+            match fields.next() {
+                //  `# branch.ab +<ahead> -<behind>`
+                Some("#") => if fields.next() == Some("branch.ab") {
+                    let mut count = || fields.next()
+                        .and_then(|n| n.get(1 ..)?.parse().ok())
+                        .unwrap_or(0);
+                    status.ahead = count();
+                    status.behind = count();
+                },
+                //  Ordinary and renamed entries carry an `XY` field: X is the
+                //  staged state and Y the unstaged state, with `.` for none.
+                Some("1") | Some("2") => {
+                    let xy = fields.next().unwrap_or("..").as_bytes();
+                    status.staged |= xy.get(0).map_or(false, |&x| x != b'.');
+                    status.dirty |= xy.get(1).map_or(false, |&y| y != b'.');
+                },
+                //  Unmerged entries need the user's attention in the tree.
+                Some("u") => status.dirty = true,
+                Some("?") => status.untracked = true,
+                _ => {},
+            }
+        }
+        status
+    }
+}
+
+/// Renders the git segment for `dir`, or nothing outside a repository.
+///
+/// The segment is the branch name followed by `+` for staged changes, `*` for
+/// unstaged changes, `?` for untracked files, and `↑N`/`↓N` for commits ahead
+/// of and behind the upstream branch.
+pub fn segment(dir: &Path) -> String {
+    let repo = match Repo::discover(dir) {
+        Some(repo) => repo,
+        None => return String::new(),
+    };
+    let mut out = match repo.head() {
+        Some(Head::Branch(name)) => name,
+        Some(Head::Detached(hash)) => format!("@{}", hash),
+        None => return String::new(),
+    };
+    if let Some(status) = repo.status() {
+        for &(set, mark) in &[
+            (status.staged, '+'),
+            (status.dirty, '*'),
+            (status.untracked, '?'),
+        ] {
+            if set {
+                out.push(mark);
+            }
+        }
+        if status.ahead > 0 {
+            out.push_str(&format!(" ↑{}", status.ahead));
+        }
+        if status.behind > 0 {
+            out.push_str(&format!(" ↓{}", status.behind));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn porcelain() {
+        let text = "\
+# branch.oid 0123456789abcdef
+# branch.head master
+# branch.upstream origin/master
+# branch.ab +2 -1
+1 M. N... 100644 100644 100644 0123 4567 src/lib.rs
+? notes.txt
+";
+        let status = Status::parse(text);
+        assert_eq!(status, Status {
+            staged: true,
+            dirty: false,
+            untracked: true,
+            ahead: 2,
+            behind: 1,
+        });
+        //  A malformed line does not stop the rest from being read.
+        let status = Status::parse("# branch.ab  -\n? notes.txt\n");
+        assert_eq!((status.ahead, status.behind, status.untracked), (0, 0, true));
+    }
+}
@@ -0,0 +1,427 @@
+//! Prompt templates
+//!
+//! The prompts are configured with a small template language in the style of
+//! `PS1`. Plain text is printed as written; backslash escapes insert
+//! information about the shell's state.
+//!
+//! | Escape      | Expands to                                             |
+//! |-------------|--------------------------------------------------------|
+//! | `\u`        | the user name                                          |
+//! | `\h`        | the host name, up to the first `.`                     |
+//! | `\H`        | the full host name                                     |
+//! | `\w`        | the working directory, with `$HOME` shown as `~`       |
+//! | `\W`        | the last component of the working directory            |
+//! | `\p`        | the full path of the working directory                 |
+//! | `\t`        | the time, as 24-hour `HH:MM:SS`                        |
+//! | `\T`        | the time, as 12-hour `HH:MM:SS`                        |
+//! | `\A`        | the time, as 24-hour `HH:MM`                           |
+//! | `\?`        | the exit status of the last command                    |
+//! | `\j`        | the number of jobs managed by the shell                |
+//! | `\g`        | the git branch and status, if in a repository          |
+//! | `\L`        | the shell level, `$SHLVL`                              |
+//! | `\s`        | the name of the shell                                  |
+//! | `\$`        | `#` for the superuser, otherwise `$`                   |
+//! | `\n`        | a newline                                              |
+//! | `\e`        | an escape character (U+001B)                           |
+//! | `\\`        | a backslash                                            |
+//! | `\c{color}` | an ANSI color or attribute, such as `red` or `bold`    |
+//! | `\[...\]`   | text that takes no space on screen, such as escapes    |
+//! | `\(C.a.b)`  | `a` if the condition `C` holds, otherwise `b`          |
+//!
+//! The conditions are `?` (the last command succeeded), `j` (there are jobs),
+//! `#` (running as the superuser), `L` (this is a nested shell), and `g` (the
+//! working directory is in a git repository). The `.`
+//! after the condition may be any character, which then separates the two
+//! branches; either branch may contain further escapes. Unknown escapes are
+//! printed as written.
+//!
+//! Colors are `black`, `red`, `green`, `yellow`, `blue`, `magenta`, `cyan`,
+//! `white`, their `bright-` variants, a number from the 256-color palette, or
+//! one of the attributes `bold`, `dim`, `italic`, `underline`, and `reset`.
+
+use std::{iter::Peekable, path::Path, str::Chars};
+
+use crate::st::State;
+
+pub mod git;
+
+/// The template used when `PS1` is not set.
+pub const DEFAULT_PS1: &str = r"\u@\H:\w % ";
+/// The template used when `PS2` is not set.
+pub const DEFAULT_PS2: &str = "> ";
+
+/// A parsed prompt template.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Prompt {
+    segments: Vec<Segment>,
+}
+
+/// The result of rendering a prompt.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Rendered {
+    /// The text to write to the terminal, including color escapes.
+    pub text: String,
+    /// The number of columns the last line of `text` occupies on screen.
+    pub width: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Text(String),
+    User,
+    Host { full: bool },
+    Cwd(Cwd),
+    Time(Clock),
+    Status,
+    Jobs,
+    Git,
+    ShellLevel,
+    Shell,
+    Dollar,
+    Color(String),
+    Invisible(Vec<Segment>),
+    Cond {
+        cond: Cond,
+        then: Vec<Segment>,
+        other: Vec<Segment>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Cwd {
+    Full,
+    Home,
+    Base,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Clock {
+    Long,
+    Twelve,
+    Short,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Cond {
+    Success,
+    Jobs,
+    Root,
+    Nested,
+    Git,
+}
+
+/// Reads a prompt template from a shell variable, falling back to `default`.
+pub fn template(state: &State, name: &str, default: &str) -> String {
+    state.var(name).unwrap_or_else(|| default.to_string())
+}
+
+// ===== impl Prompt =====
+
+impl Prompt {
+    /// Parses a template. Parsing never fails: anything that is not a
+    /// recognized escape is kept as literal text.
+    pub fn new(template: &str) -> Self {
+        let mut chars = template.chars().peekable();
+        let (segments, _) = parse(&mut chars, &[], false);
+        Self { segments }
+    }
+
+    /// Renders the template against the current state of the shell.
+    pub fn render(&self, state: &State) -> Rendered {
+        let mut out = Rendered::default();
+        render(&self.segments, state, &mut out, true);
+        out
+    }
+}
+
+/// Parses segments until the input ends or one of `stops` is found unescaped.
+/// When `bracket` is set, the escape `\]` also ends the segments.
+///
+/// Returns the segments and the stop character that ended them, if any.
+fn parse(
+    chars: &mut Peekable<Chars>,
+    stops: &[char],
+    bracket: bool,
+) -> (Vec<Segment>, Option<char>) {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    //  Moves accumulated literal text into its own segment before an escape.
+    fn flush(text: &mut String, segments: &mut Vec<Segment>) {
+        if !text.is_empty() {
+            segments.push(Segment::Text(text.split_off(0)));
+        }
+    }
+    while let Some(c) = chars.next() {
+        if stops.contains(&c) {
+            flush(&mut text, &mut segments);
+            return (segments, Some(c));
+        }
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        let seg = match chars.next() {
+            Some('u') => Segment::User,
+            Some('h') => Segment::Host { full: false },
+            Some('H') => Segment::Host { full: true },
+            Some('w') => Segment::Cwd(Cwd::Home),
+            Some('W') => Segment::Cwd(Cwd::Base),
+            Some('p') => Segment::Cwd(Cwd::Full),
+            Some('t') => Segment::Time(Clock::Long),
+            Some('T') => Segment::Time(Clock::Twelve),
+            Some('A') => Segment::Time(Clock::Short),
+            Some('?') => Segment::Status,
+            Some('j') => Segment::Jobs,
+            Some('g') => Segment::Git,
+            Some('L') => Segment::ShellLevel,
+            Some('s') => Segment::Shell,
+            Some('$') => Segment::Dollar,
+            Some('n') => {
+                text.push('\n');
+                continue;
+            },
+            Some('e') => {
+                text.push('\u{1B}');
+                continue;
+            },
+            Some('\\') => {
+                text.push('\\');
+                continue;
+            },
+            Some('c') if chars.peek() == Some(&'{') => {
+                chars.next();
+                Segment::Color(chars.by_ref().take_while(|&c| c != '}').collect())
+            },
+            Some('[') => Segment::Invisible(parse(chars, &[], true).0),
+            Some(']') if bracket => {
+                flush(&mut text, &mut segments);
+                return (segments, Some(']'));
+            },
+            Some('(') => match parse_cond(chars) {
+                Some(seg) => seg,
+                None => {
+                    text.push_str("\\(");
+                    continue;
+                },
+            },
+            Some(other) => {
+                text.push('\\');
+                text.push(other);
+                continue;
+            },
+            None => {
+                text.push('\\');
+                continue;
+            },
+        };
+        flush(&mut text, &mut segments);
+        segments.push(seg);
+    }
+    flush(&mut text, &mut segments);
+    (segments, None)
+}
+
+/// Parses the body of a `\(C.then.else)` conditional, after the `\(`.
+fn parse_cond(chars: &mut Peekable<Chars>) -> Option<Segment> {
+    let cond = match chars.peek()? {
+        '?' => Cond::Success,
+        'j' => Cond::Jobs,
+        '#' => Cond::Root,
+        'L' => Cond::Nested,
+        'g' => Cond::Git,
+        _ => return None,
+    };
+    chars.next();
+    let sep = chars.next()?;
+    let (then, _) = parse(chars, &[sep], false);
+    let (other, _) = parse(chars, &[')'], false);
+    Some(Segment::Cond { cond, then, other })
+}
+
+/// Renders segments onto the end of `out`. Text is only counted towards the
+/// on-screen width when `visible` is set.
+fn render(segments: &[Segment], state: &State, out: &mut Rendered, visible: bool) {
+    for seg in segments {
+        let text = match seg {
+            Segment::Text(t) => t.clone(),
+            Segment::User => state.user.clone(),
+            Segment::Host { full: true } => state.host.clone(),
+            Segment::Host { full: false } => {
+                state.host.split('.').next().unwrap_or("").to_string()
+            },
+            Segment::Cwd(Cwd::Full) => state.pwd.display().to_string(),
+            Segment::Cwd(Cwd::Home) => abbreviate(&state.pwd, state.home().as_ref().map(AsRef::as_ref)),
+            Segment::Cwd(Cwd::Base) => state.pwd.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| state.pwd.display().to_string()),
+            Segment::Time(clock) => {
+                let (h, m, s) = local_time();
+                match clock {
+                    Clock::Long => format!("{:02}:{:02}:{:02}", h, m, s),
+                    Clock::Twelve => {
+                        format!("{:02}:{:02}:{:02}", (h + 11) % 12 + 1, m, s)
+                    },
+                    Clock::Short => format!("{:02}:{:02}", h, m),
+                }
+            },
+            Segment::Status => state.status.to_string(),
+            //  ysh does not run background jobs yet, so there are never any.
+            Segment::Jobs => "0".to_string(),
+            Segment::Git => git::segment(&state.pwd),
+            Segment::ShellLevel => shell_level(state).to_string(),
+            Segment::Shell => "ysh".to_string(),
+            Segment::Dollar => if is_root() { "#" } else { "$" }.to_string(),
+            Segment::Color(name) => {
+                out.text.push_str(&color(name).unwrap_or_default());
+                continue;
+            },
+            Segment::Invisible(inner) => {
+                render(inner, state, out, false);
+                continue;
+            },
+            Segment::Cond { cond, then, other } => {
+                let holds = match cond {
+                    Cond::Success => state.status == 0,
+                    Cond::Jobs => false,
+                    Cond::Root => is_root(),
+                    Cond::Nested => shell_level(state) > 1,
+                    Cond::Git => git::Repo::discover(&state.pwd).is_some(),
+                };
+                render(if holds { then } else { other }, state, out, visible);
+                continue;
+            },
+        };
+        if visible {
+            out.width = match text.rfind('\n') {
+                Some(n) => text[n + 1 ..].chars().count(),
+                None => out.width + text.chars().count(),
+            };
+        }
+        out.text.push_str(&text);
+    }
+}
+
+/// Displays `path`, replacing a leading `home` directory with `~`.
+pub fn abbreviate(path: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|h| path.strip_prefix(h).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Translates a color or attribute name into its ANSI escape sequence.
+fn color(name: &str) -> Option<String> {
+    const COLORS: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let code = match name {
+        "reset" => "0".to_string(),
+        "bold" => "1".to_string(),
+        "dim" => "2".to_string(),
+        "italic" => "3".to_string(),
+        "underline" => "4".to_string(),
+        _ => if let Ok(n) = name.parse::<u8>() {
+            format!("38;5;{}", n)
+        } else if name.starts_with("bright-") {
+            let n = COLORS.iter().position(|&c| c == &name[7 ..])?;
+            (90 + n).to_string()
+        } else {
+            let n = COLORS.iter().position(|&c| c == name)?;
+            (30 + n).to_string()
+        },
+    };
+    Some(format!("\u{1B}[{}m", code))
+}
+
+fn shell_level(state: &State) -> u32 {
+    state.var("SHLVL").and_then(|l| l.parse().ok()).unwrap_or(1)
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(windows)]
+fn is_root() -> bool {
+    false
+}
+
+/// Gets the current local wall-clock time as hours, minutes, and seconds.
+#[cfg(unix)]
+fn local_time() -> (u32, u32, u32) {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        (tm.tm_hour as u32, tm.tm_min as u32, tm.tm_sec as u32)
+    }
+}
+
+/// Windows has no `localtime_r`, so the prompt shows UTC there.
+#[cfg(windows)]
+fn local_time() -> (u32, u32, u32) {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    (((secs / 3600) % 24) as u32, ((secs / 60) % 60) as u32, (secs % 60) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn state() -> State {
+        State {
+            pwd: PathBuf::from("/srv/ysh"),
+            host: "box.example.com".into(),
+            user: "ferris".into(),
+            ..State::default()
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        let out = Prompt::new(r"\u@\h:\W \\ % ").render(&state());
+        assert_eq!(out.text, r"ferris@box:ysh \ % ");
+        assert_eq!(out.width, out.text.len());
+
+        let out = Prompt::new(r"\H \p").render(&state());
+        assert_eq!(out.text, "box.example.com /srv/ysh");
+    }
+
+    #[test]
+    fn colors_take_no_width() {
+        let out = Prompt::new(r"\c{red}\u\c{reset} \[\e[1m\]>").render(&state());
+        assert_eq!(out.text, "\u{1B}[31mferris\u{1B}[0m \u{1B}[1m>");
+        assert_eq!(out.width, "ferris >".len());
+    }
+
+    #[test]
+    fn conditionals() {
+        let tpl = Prompt::new(r"\(?.ok.\c{red}\?\c{reset}) %");
+        let mut st = state();
+        assert_eq!(tpl.render(&st).text, "ok %");
+        st.status = 2;
+        assert_eq!(tpl.render(&st).text, "\u{1B}[31m2\u{1B}[0m %");
+    }
+
+    #[test]
+    fn multiline_width() {
+        let out = Prompt::new(r"\p\n% ").render(&state());
+        assert_eq!(out.width, 2);
+    }
+
+    #[test]
+    fn home() {
+        let home = Path::new("/home/ferris");
+        assert_eq!(abbreviate(Path::new("/home/ferris"), Some(home)), "~");
+        assert_eq!(abbreviate(Path::new("/home/ferris/src"), Some(home)), "~/src");
+        assert_eq!(abbreviate(Path::new("/home/ferrous"), Some(home)), "/home/ferrous");
+        assert_eq!(abbreviate(Path::new("/tmp"), None), "/tmp");
+    }
+}
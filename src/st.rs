@@ -1,124 +1,489 @@
 //! Functions and types dealing with the `State` of the shell
 
 use std::{
+    collections::{BTreeMap, HashMap},
     env,
-    io::{self, Write},
-    path::{Path, PathBuf},
+    fs::{self, File},
+    io::{self, Read, Write},
+    mem,
+    path::{Component, Path, PathBuf},
+    process::ExitStatus,
+    rc::Rc,
     str,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::{
-    ast::{Builtin, Cmd},
-    parse::Parse,
-    term::Term,
+    arith,
+    ast::{Command, Dirs, Script, StackIndex, SyntaxError},
+    exec::Flow,
+    frecency::Frecency,
+    key::{self, Key},
+    parse::{Parse, ParseError},
+    prompt,
+    term::{self, Term},
+    token,
 };
 
 use crossterm::{input, Screen};
-use duct::cmd;
-use failure::{bail, Error};
+use failure::{bail, Error, Fail};
 #[cfg(windows)]
 use winapi::um::winbase::{GetComputerNameA, GetUserNameA};
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct State {
+    /// The name of the shell, or of the script it is running, which is `$0`.
+    pub name: String,
+    /// Whether the shell is reading commands from the terminal, and so has it
+    /// in raw mode.
+    pub interactive: bool,
+    /// Whether this is a login shell, which runs the profile when it starts.
+    pub login: bool,
     pub pwd: PathBuf,
     pub host: String,
     pub user: String,
+    /// The exit status of the most recent command.
+    pub status: i32,
+    /// The directory stack managed by `pushd` and `popd`, not including the
+    /// working directory, which is always the top entry. The most recently
+    /// pushed directory is first.
+    pub dirs: Vec<PathBuf>,
+    /// Every directory the shell has visited, ranked for `z`.
+    pub frecency: Frecency,
+    pub options: Options,
+    /// How many loops are running, for `break` and `continue`.
+    crate loops: usize,
+    /// How many files are being run by `source`, which `return` may leave.
+    crate sourcing: usize,
+    /// Whether a startup file ran `exit`, so that the shell reads no commands.
+    crate exited: bool,
+    /// The positional parameters, `$1` onward. These are the arguments of the
+    /// running function, or else of the script.
+    pub args: Vec<String>,
+    /// The aliases defined with `alias`, by name.
+    pub aliases: BTreeMap<String, String>,
+    /// The aliases being expanded, which are not expanded again in the
+    /// commands they expand to.
+    crate aliasing: Vec<String>,
+    /// The input of a group or subshell with a here-document, for the commands
+    /// in it that have no input of their own. They share the one open file, so
+    /// that each command reads on from where the one before it stopped.
+    crate input: Option<Rc<File>>,
+    /// The functions defined with `name() { ...; }`, by name.
+    pub functions: HashMap<String, Rc<Vec<Command>>>,
+    /// The global shell variables, by name. Those that are exported make up
+    /// the environment of the programs the shell runs.
+    pub vars: BTreeMap<String, Var>,
+    /// The variables declared with `local` in each running function, innermost
+    /// last.
+    crate locals: Vec<BTreeMap<String, Var>>,
+}
+
+/// A shell variable, with its attributes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Var {
+    /// The value of the variable, or `None` if it has been declared, as by
+    /// `export name`, but not set.
+    pub value: Option<Value>,
+    /// Whether the variable is in the environment of the programs the shell
+    /// runs.
+    pub exported: bool,
+    /// Whether the variable may no longer be set or unset.
+    pub readonly: bool,
+}
+
+/// The value of a shell variable: a string, or an array of them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    /// An indexed array, as set by `arr=(a b c)`. There may be gaps between
+    /// the indices of its elements.
+    Indexed(BTreeMap<usize, String>),
+    /// An associative array, declared with `declare -A`, whose elements are
+    /// named by strings.
+    Assoc(BTreeMap<String, String>),
+}
+
+/// The subscript of an element of an array: an index in an indexed array, or
+/// a key in an associative array. A scalar is element `0` of itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Subscript {
+    Index(usize),
+    Key(String),
+}
+
+/// An attempt to set or unset a readonly variable.
+#[derive(Clone, Debug, Eq, PartialEq, Fail)]
+#[fail(display = "{}: readonly variable", _0)]
+pub struct ReadOnly(pub String);
+
+impl Value {
+    /// Gets the value as a single string. That of an array is its element `0`,
+    /// as `$arr` is.
+    pub fn scalar(&self) -> Option<&str> {
+        self.element(&Subscript::Index(0))
+    }
+
+    /// Gets an element of the value.
+    pub fn element(&self, sub: &Subscript) -> Option<&str> {
+        match (self, sub) {
+            (Value::Scalar(value), Subscript::Index(0)) => Some(value),
+            (Value::Indexed(values), Subscript::Index(n)) => values.get(n).map(String::as_str),
+            (Value::Assoc(values), Subscript::Index(n)) => values.get(&n.to_string()).map(String::as_str),
+            (Value::Assoc(values), Subscript::Key(key)) => values.get(key).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// Gets every element of the value, in the order of their subscripts.
+    pub fn elements(&self) -> Vec<&str> {
+        match self {
+            Value::Scalar(value) => vec![value],
+            Value::Indexed(values) => values.values().map(String::as_str).collect(),
+            Value::Assoc(values) => values.values().map(String::as_str).collect(),
+        }
+    }
+
+    /// Sets an element of the value. A scalar becomes an indexed array, unless
+    /// the element is `0`, which it is itself. Only an associative array has
+    /// keys, so a key leaves any other value as it is.
+    pub fn set_element(&mut self, sub: Subscript, value: String) {
+        match (&mut *self, sub) {
+            (Value::Assoc(values), Subscript::Key(key)) => {
+                values.insert(key, value);
+            },
+            (Value::Assoc(values), Subscript::Index(n)) => {
+                values.insert(n.to_string(), value);
+            },
+            (Value::Indexed(values), Subscript::Index(n)) => {
+                values.insert(n, value);
+            },
+            (Value::Scalar(old), Subscript::Index(0)) => *old = value,
+            (Value::Scalar(old), Subscript::Index(n)) => {
+                let mut values = BTreeMap::new();
+                values.insert(0, mem::replace(old, String::new()));
+                values.insert(n, value);
+                *self = Value::Indexed(values);
+            },
+            (_, Subscript::Key(_)) => {},
+        }
+    }
+
+    /// Removes an element of the value. Removing element `0` of a scalar
+    /// leaves it empty.
+    pub fn remove(&mut self, sub: &Subscript) {
+        match (self, sub) {
+            (Value::Assoc(values), Subscript::Key(key)) => drop(values.remove(key)),
+            (Value::Assoc(values), Subscript::Index(n)) => drop(values.remove(&n.to_string())),
+            (Value::Indexed(values), Subscript::Index(n)) => drop(values.remove(n)),
+            (Value::Scalar(value), Subscript::Index(0)) => value.clear(),
+            _ => {},
+        }
+    }
+
+    /// The index after the last element of an indexed array, where `arr+=(x)`
+    /// puts `x`, and from which negative indices count back.
+    pub fn end(&self) -> usize {
+        match self {
+            Value::Indexed(values) => values.keys().next_back().map_or(0, |n| n + 1),
+            _ => 1,
+        }
+    }
+}
+
+/// Shell options, which are set and unset with `shopt`.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Patterns that match no files expand to nothing, instead of themselves.
+    pub nullglob: bool,
+    /// Patterns that match no files are an error.
+    pub failglob: bool,
+    /// Patterns match names beginning with `.`.
+    pub dotglob: bool,
+}
+
+impl Options {
+    /// Gets a mutable reference to an option by name.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "nullglob" => Some(&mut self.nullglob),
+            "failglob" => Some(&mut self.failglob),
+            "dotglob" => Some(&mut self.dotglob),
+            _ => None,
+        }
+    }
+
+    /// Lists the options by name, with their current values.
+    pub fn list(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("dotglob", self.dotglob),
+            ("failglob", self.failglob),
+            ("nullglob", self.nullglob),
+        ]
+    }
 }
 
 impl State {
+    /// Sets up the shell as it starts, and runs its startup files.
+    ///
+    /// A login shell runs the profile, `/etc/ysh_profile` and then
+    /// `~/.ysh_profile`. An interactive shell then runs the rc files,
+    /// `/etc/yshrc` and then `~/.yshrc`. Any of these that does not exist is
+    /// skipped, and `exit` in one of them stops the shell before it reads any
+    /// commands. The directory stack and the history of `z` are loaded again
+    /// if the startup files moved them.
     pub fn init(self, screen: &mut Screen) -> Result<Self, Error> {
         let host = hostname()?;
         let user = user()?;
-        env::set_var("HOST", &host);
-        env::set_var("USER", &user);
-
-        let pwd = env::current_dir()?;
-
-        let this = Self {
-            pwd,
+        let mut this = Self {
             host,
             user,
             ..self
         };
-        screen.reset(&this)?;
+        //  The shell starts with the variables of its environment, which it
+        //  passes on. Those that are not valid Unicode are left out.
+        for (name, value) in env::vars_os() {
+            if let (Ok(name), Ok(value)) = (name.into_string(), value.into_string()) {
+                let value = Some(Value::Scalar(value));
+                this.vars.insert(name, Var { value, exported: true, readonly: false });
+            }
+        }
+        this.put("HOST", this.host.clone());
+        this.put("USER", this.user.clone());
+
+        //  Each nested shell is one level deeper than the shell that ran it.
+        let level = this.var("SHLVL")
+            .and_then(|l| l.parse::<u32>().ok())
+            .unwrap_or(0);
+        this.put("SHLVL", (level + 1).to_string());
+
+        //  Prefer the logical working directory inherited in `$PWD`, as long
+        //  as it really is the working directory.
+        let cwd = env::current_dir()?;
+        this.pwd = this.var("PWD")
+            .map(PathBuf::from)
+            .filter(|pwd| pwd.is_absolute())
+            .filter(|pwd| pwd.canonicalize().ok() == cwd.canonicalize().ok())
+            .unwrap_or(cwd);
+        let pwd = this.pwd.display().to_string();
+        this.put("PWD", pwd);
+
+        this.load_dirs();
+        this.load_frecency();
+        let saved = (this.dirs_file(), this.z_file());
+
+        if this.interactive {
+            term::set_raw(true);
+            //  The shell must survive the SIGINT that the terminal sends to
+            //  every process in the foreground when Ctrl-C is pressed during a
+            //  job.
+            catch_interrupt();
+            screen.clear()?;
+        }
+        let home = this.home();
+        let user_file = |name: &str| home.as_ref().map(|home| home.join(name));
+        let mut startup = Vec::new();
+        if this.login {
+            startup.push(Some(PathBuf::from("/etc/ysh_profile")));
+            startup.push(user_file(".ysh_profile"));
+        }
+        if this.interactive {
+            startup.push(Some(PathBuf::from("/etc/yshrc")));
+            startup.push(user_file(".yshrc"));
+        }
+        for file in startup.into_iter().flatten().filter(|file| file.is_file()) {
+            if this.cooked(screen, |this, screen| this.source(&file, screen))? == Flow::Exit {
+                this.exited = true;
+                return Ok(this);
+            }
+        }
+        //  The startup files may keep the saved state somewhere else.
+        if this.dirs_file() != saved.0 {
+            this.load_dirs();
+        }
+        if this.z_file() != saved.1 {
+            this.load_frecency();
+        }
 
         Ok(this)
     }
 
+    /// Runs a whole script, such as a file given to `ysh` or the text given to
+    /// `ysh -c`, without prompting or reading from the terminal.
+    pub fn script(&mut self, text: &str, screen: &mut Screen) -> Result<(), Error> {
+        if self.exited {
+            return Ok(());
+        }
+        match Script::parse_from(text) {
+            Ok(script) => {
+                self.exec(&script.commands, screen)?;
+            },
+            Err(ParseError::NoInput) => {},
+            Err(e) => {
+                self.status = 2;
+                screen.error(&self.name, e)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Reads and runs commands from an input that is not a terminal, such as a
+    /// pipe, until `exit` or the end of the input.
+    ///
+    /// Each command runs as soon as all of its lines have been read, without
+    /// prompts, line editing, or raw mode. The input is read one byte at a time
+    /// up to each newline, so that a program the commands run can read the
+    /// lines after them, as `cat` does in `printf 'cat\nhello\n' | ysh`.
+    pub fn run_input(&mut self, input: impl Read, screen: &mut Screen) -> Result<(), Error> {
+        if self.exited {
+            return Ok(());
+        }
+        let mut text = String::new();
+        let mut bytes = input.bytes();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let mut ended = false;
+            for byte in &mut bytes {
+                match byte? {
+                    b'\n' => {
+                        ended = true;
+                        break;
+                    },
+                    byte => line.push(byte),
+                }
+            }
+            if !ended && line.is_empty() {
+                break;
+            }
+            text.push_str(str::from_utf8(&line)?);
+            text.push('\n');
+            if token::incomplete(&text) {
+                continue;
+            }
+            match Script::parse_from(&text) {
+                Err(ParseError::Other(SyntaxError::Incomplete)) => continue,
+                Err(ParseError::NoInput) => {},
+                Err(e) => {
+                    self.status = 2;
+                    screen.error(&self.name, e)?;
+                },
+                Ok(script) => {
+                    if self.exec(&script.commands, screen)? == Flow::Exit {
+                        return Ok(());
+                    }
+                },
+            }
+            text.clear();
+        }
+        if !text.trim().is_empty() {
+            self.status = 2;
+            screen.error(&self.name, SyntaxError::Incomplete)?;
+        }
+        Ok(())
+    }
+
+    /// Reads and runs commands from the terminal, until `exit` or Ctrl-D.
+    ///
+    /// The shell must have been set up by `init` as an interactive shell.
     pub fn run(&mut self, mut screen: Screen) -> Result<(), Error> {
+        if self.exited {
+            return Ok(());
+        }
+        screen.prompt(&self)?;
         let mut line = Vec::new();
         loop {
             let stdin = input(&screen);
-            match stdin.read_char()? {
-                // ESC Key
-                '\u{001B}' => break,
-                // Backspace and Delete because on *nix it can send either or to mean the same thing
-                '\u{0008}' | '\u{007F}' => {
+            let first = stdin.read_char()?;
+            let key = Key::read(first, || {
+                if key::pending() {
+                    stdin.read_char().ok()
+                } else {
+                    None
+                }
+            });
+            match key {
+                Key::Backspace => {
                     if line.len() > 0 {
                         line.pop();
                         screen.backspace()?;
                     }
                 },
-                '\u{000D}' /* Enter */ => {
-                    match Cmd::parse_from(str::from_utf8(&line)?) {
-                        Err(_e) => {
-                            // TODO(eliza): handle parse errors!
+                //  Ctrl-C abandons the line being edited and starts afresh.
+                Key::Ctrl('c') => {
+                    screen.write(b"^C")?;
+                    screen.newline()?;
+                    line.clear();
+                    screen.prompt(&self)?;
+                },
+                //  Ctrl-D is end-of-input, but only when there is no input.
+                Key::Ctrl('d') => {
+                    if line.is_empty() {
+                        screen.newline()?;
+                        break;
+                    }
+                },
+                Key::Enter => {
+                    let text = str::from_utf8(&line)?;
+                    //  An unterminated quote, meta-sequence, or compound
+                    //  command continues on the next line.
+                    let script = if token::incomplete(text) {
+                        None
+                    } else {
+                        match Script::parse_from(text) {
+                            Err(ParseError::Other(SyntaxError::Incomplete)) => None,
+                            script => Some(script),
+                        }
+                    };
+                    screen.newline()?;
+                    match script {
+                        None => {
+                            line.push(b'\n');
+                            screen.continuation(&self)?;
                             continue;
                         },
-                        Ok(Cmd::Builtin(Builtin::Clear)) => {
-                            screen.reset(&self)?;
-                        },
-                        Ok(Cmd::Builtin(Builtin::Cd(to))) => {
-                            screen.newline()?;
-                            self.cd(to)
-                                .or_else(|e| {
-                                    screen.error("cd", &e)
-                                })?;
-
-                            screen.prompt(&self)?;
+                        Some(Err(ParseError::NoInput)) => {},
+                        Some(Err(e)) => {
+                            self.status = 2;
+                            screen.error("ysh", e)?;
                         },
-                        Ok(Cmd::Builtin(Builtin::Exit)) => {
-                            break;
+                        Some(Ok(script)) => {
+                            let flow = self.cooked(&mut screen, |this, screen| {
+                                this.exec(&script.commands, screen)
+                            })?;
+                            if flow == Flow::Exit {
+                                break;
+                            }
                         },
-                        Ok(Cmd::Invoke(ref c)) => {
-                            screen.newline()?;
-                            cmd(c.command, c.args.clone())
-                                .unchecked()
-                                .stdout_capture()
-                                .stderr_capture()
-                                .run()
-                                .map_err(Into::into)
-                                .and_then(|exec| {
-                                    if &exec.stdout != b"" {
-                                        screen.command_output(&exec.stdout)?;
-                                    } else if &exec.stderr != b"" {
-                                        screen.command_output(&exec.stderr)?;
-                                    }
-                                    Ok(())
-                                })
-                                .or_else(|err: Error| {
-                                    if err.find_root_cause()
-                                        .downcast_ref::<io::Error>()
-                                        .iter()
-                                        .any(|e| e.kind() == io::ErrorKind::NotFound)
-                                    {
-                                        screen.not_found(&c.command.to_string_lossy())
-                                    } else {
-                                        screen.error("ysh", err)
-                                    }
-                                })?;
-
-                            screen.prompt(&self)?;
-                        }
                     }
+                    screen.prompt(&self)?;
                     line.clear();
                 },
+                //  Tab completes the fragments given to `z` into the path of
+                //  the directory it would jump to.
+                Key::Tab => {
+                    let text = str::from_utf8(&line)?.to_string();
+                    if text.starts_with("z ") {
+                        let rest = &text[2 ..];
+                        let fragments = rest.split_whitespace().collect::<Vec<_>>();
+                        if let Some(dir) = self.frecency.matches(&fragments).first() {
+                            for _ in 0 .. rest.len() {
+                                screen.backspace()?;
+                            }
+                            let dir = dir.display().to_string();
+                            line.truncate(2);
+                            line.extend_from_slice(dir.as_bytes());
+                            screen.write(dir.as_bytes())?;
+                        }
+                    }
+                },
                 // Only printable ASCII characters
-                c if c as u8 >= 32 && c as u8 <= 126 => {
+                Key::Char(c) if c as u32 >= 32 && c as u32 <= 126 => {
                     line.push(c as u8);
                     screen.write(&[c as u8])?;
                 },
+                //  Arrow keys, ESC, and the like have no line-editing meaning
+                //  yet, but are consumed whole so they don't leak into input.
                 _ => {}
             }
             screen.flush()?;
@@ -126,14 +491,497 @@ impl State {
         Ok(())
     }
 
+    /// Runs commands with the terminal out of raw mode, if the shell is
+    /// interactive.
+    ///
+    /// Out of raw mode, the terminal turns Ctrl-C into SIGINT, which stops the
+    /// job that is running, and the shell between commands (see `exec`).
+    crate fn cooked(
+        &mut self,
+        screen: &mut Screen,
+        run: impl FnOnce(&mut Self, &mut Screen) -> Result<Flow, Error>,
+    ) -> Result<Flow, Error> {
+        if !self.interactive {
+            return run(self, screen);
+        }
+        //  A Ctrl-C from before the commands started is not for them.
+        take_interrupt();
+        screen.disable_raw_modes()?;
+        term::set_raw(false);
+        let flow = run(self, screen);
+        screen.enable_raw_modes()?;
+        term::set_raw(true);
+        flow
+    }
+
+    /// Gets the home directory of the user running the shell.
+    ///
+    /// This is `$HOME` if it is set, and otherwise the home directory listed
+    /// for the user in the system registry.
+    pub fn home(&self) -> Option<PathBuf> {
+        self.var("HOME")
+            .map(PathBuf::from)
+            .or_else(|| home_of(&self.user))
+    }
+
+    /// Runs the `cd` builtin.
+    ///
+    /// With no directory, this goes to `$HOME`; the directory `-` goes back to
+    /// `$OLDPWD`. Relative directories that do not start with `.` or `..` are
+    /// searched for in each entry of `$CDPATH` before the working directory.
+    ///
+    /// Returns the new working directory when it should be announced to the
+    /// user: after `cd -`, or when it was found through `$CDPATH`.
+    pub fn change_dir(
+        &mut self,
+        to: Option<&str>,
+        physical: bool,
+    ) -> Result<Option<PathBuf>, Error> {
+        let to = match to {
+            None => match self.var("HOME") {
+                Some(home) if !home.is_empty() => home,
+                _ => bail!("HOME not set"),
+            },
+            Some("-") => match self.var("OLDPWD") {
+                Some(old) if !old.is_empty() => {
+                    self.chdir(old, physical)?;
+                    return Ok(Some(self.pwd.clone()));
+                },
+                _ => bail!("OLDPWD not set"),
+            },
+            Some(to) => to.to_string(),
+        };
+
+        let path = Path::new(&to);
+        let searchable = path.is_relative() && match path.components().next() {
+            Some(Component::CurDir) | Some(Component::ParentDir) => false,
+            _ => true,
+        };
+        if searchable {
+            let cdpath = self.var("CDPATH").unwrap_or_default();
+            for entry in env::split_paths(&cdpath) {
+                //  An empty entry is the working directory, and that match is
+                //  not announced, since it is where the user expected to go.
+                let announce = !entry.as_os_str().is_empty();
+                let candidate = self.pwd.join(entry).join(path);
+                if candidate.is_dir() {
+                    self.chdir(&candidate, physical)?;
+                    return Ok(if announce { Some(self.pwd.clone()) } else { None });
+                }
+            }
+        }
+        self.chdir(path, physical)?;
+        Ok(None)
+    }
+
+    /// Changes the working directory of the shell.
+    ///
+    /// This is the logical form of `cd`: the new directory is written in terms
+    /// of the path the user followed, including any symbolic links.
     pub fn cd<P: AsRef<Path>>(&mut self, to: P) -> io::Result<()> {
-        let to = to.as_ref().canonicalize()?;
+        self.chdir(to, false)
+    }
+
+    /// Changes the working directory, either logically, keeping symbolic links
+    /// as written, or physically, resolving them.
+    ///
+    /// `$PWD` and `$OLDPWD` are updated to match, and exported, so that child
+    /// processes see the same working directory as the shell.
+    pub fn chdir<P: AsRef<Path>>(&mut self, to: P, physical: bool) -> io::Result<()> {
+        let to = if physical {
+            self.pwd.join(to).canonicalize()?
+        } else {
+            normalize(&self.pwd.join(to))
+        };
         env::set_current_dir(&to)?;
-        self.pwd = to;
+        let old = mem::replace(&mut self.pwd, to);
+        self.put("OLDPWD", old.display().to_string());
+        let pwd = self.pwd.display().to_string();
+        self.put("PWD", pwd);
+        self.frecency.visit(&self.pwd);
         Ok(())
     }
 }
 
+// ===== Directory stack =====
+
+impl State {
+    /// Gets the whole directory stack, starting with the working directory.
+    pub fn dir_stack(&self) -> Vec<&Path> {
+        Some(self.pwd.as_path()).into_iter()
+            .chain(self.dirs.iter().map(PathBuf::as_path))
+            .collect()
+    }
+
+    /// Changes to a new directory, pushing the working directory on the stack.
+    pub fn push_dir(&mut self, to: &str) -> Result<(), Error> {
+        let old = self.pwd.clone();
+        self.change_dir(Some(to), false)?;
+        self.dirs.insert(0, old);
+        self.save_dirs();
+        Ok(())
+    }
+
+    /// Exchanges the working directory with the top of the stack.
+    pub fn swap_dirs(&mut self) -> Result<(), Error> {
+        if self.dirs.is_empty() {
+            bail!("no other directory");
+        }
+        let to = self.dirs[0].clone();
+        let old = self.pwd.clone();
+        self.cd(&to)?;
+        self.dirs[0] = old;
+        self.save_dirs();
+        Ok(())
+    }
+
+    /// Rotates the stack so that the entry at `index` becomes the working
+    /// directory.
+    pub fn rotate_dirs(&mut self, index: StackIndex) -> Result<(), Error> {
+        let mut stack = self.dir_stack().into_iter()
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        let n = match index.resolve(stack.len()) {
+            Some(n) => n,
+            None => bail!("directory stack index out of range"),
+        };
+        stack.rotate_left(n);
+        self.cd(&stack[0])?;
+        self.dirs = stack.split_off(1);
+        self.save_dirs();
+        Ok(())
+    }
+
+    /// Removes an entry from the stack, by default the top one. Removing the
+    /// top entry changes to the directory beneath it.
+    pub fn pop_dir(&mut self, index: Option<StackIndex>) -> Result<(), Error> {
+        if self.dirs.is_empty() {
+            bail!("directory stack empty");
+        }
+        let len = self.dirs.len() + 1;
+        match index.unwrap_or(StackIndex::Top(0)).resolve(len) {
+            Some(0) => {
+                let to = self.dirs[0].clone();
+                self.cd(&to)?;
+                self.dirs.remove(0);
+            },
+            Some(n) => drop(self.dirs.remove(n - 1)),
+            None => bail!("directory stack index out of range"),
+        }
+        self.save_dirs();
+        Ok(())
+    }
+
+    /// Prints the directory stack, as the `dirs` builtin.
+    crate fn print_dirs(&self, screen: &mut Screen, opts: &Dirs) -> Result<(), Error> {
+        let home = if opts.long { None } else { self.home() };
+        let entries = self.dir_stack().into_iter()
+            .map(|dir| prompt::abbreviate(dir, home.as_ref().map(AsRef::as_ref)));
+        if opts.verbose || opts.lines {
+            for (n, dir) in entries.enumerate() {
+                if opts.verbose {
+                    write!(screen, "{:2}  ", n)?;
+                }
+                write!(screen, "{}", dir)?;
+                screen.newline()?;
+            }
+        } else {
+            write!(screen, "{}", entries.collect::<Vec<_>>().join(" "))?;
+            screen.newline()?;
+        }
+        Ok(())
+    }
+
+    /// Gets the file the directory stack is kept in between sessions, if the
+    /// user has asked for that by setting `$YSH_DIRSTACK_FILE`.
+    fn dirs_file(&self) -> Option<PathBuf> {
+        self.var("YSH_DIRSTACK_FILE")
+            .filter(|file| !file.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Gets the file in which `z` keeps its history, `$YSH_Z_DATA` or else
+    /// `~/.ysh_z`.
+    fn z_file(&self) -> Option<PathBuf> {
+        Frecency::default_file(self.var("YSH_Z_DATA"), self.home().as_ref().map(AsRef::as_ref))
+    }
+
+    /// Loads the history of `z` saved by previous sessions.
+    fn load_frecency(&mut self) {
+        self.frecency = self.z_file().map(Frecency::load).unwrap_or_default();
+    }
+
+    /// Restores the directory stack saved by a previous session. Directories
+    /// that no longer exist are dropped.
+    fn load_dirs(&mut self) {
+        let text = match self.dirs_file().and_then(|f| fs::read_to_string(f).ok()) {
+            Some(text) => text,
+            None => return,
+        };
+        self.dirs = text.lines()
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute() && dir.is_dir())
+            .collect();
+    }
+
+    /// Saves the directory stack for future sessions.
+    ///
+    /// Persistence is a convenience, so failing to write the file is not
+    /// reported as a failure of the command that changed the stack.
+    crate fn save_dirs(&self) {
+        if let Some(file) = self.dirs_file() {
+            let text = self.dirs.iter()
+                .map(|dir| format!("{}\n", dir.display()))
+                .collect::<String>();
+            drop(fs::write(file, text));
+        }
+    }
+}
+
+// ===== Variables =====
+
+//  Shell variables are global, except for those declared with `local` in a
+//  function. Functions see the locals of the functions that called them, so
+//  the innermost declaration of a name wins. The shell's own process
+//  environment is only read once, when it starts.
+impl State {
+    /// Finds a shell variable, which is the innermost local variable of that
+    /// name if there is one.
+    pub fn lookup(&self, name: &str) -> Option<&Var> {
+        self.locals.iter().rev()
+            .find_map(|frame| frame.get(name))
+            .or_else(|| self.vars.get(name))
+    }
+
+    /// Gets the value of a shell variable, as a single string.
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.value(name).and_then(Value::scalar).map(String::from)
+    }
+
+    /// Gets the value of a shell variable, which may be an array.
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.lookup(name).and_then(|var| var.value.as_ref())
+    }
+
+    /// Gets every element of an array, or the value of a scalar, as for
+    /// `${arr[@]}`.
+    pub fn elements(&self, name: &str) -> Vec<String> {
+        self.value(name)
+            .map(|value| value.elements().into_iter().map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// Joins fields, such as the positional parameters for `$*`, with the
+    /// first character of `$IFS`, or with nothing if it is empty.
+    pub fn join_fields(&self, fields: &[String]) -> String {
+        let sep = match self.var("IFS") {
+            Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+            None => " ".to_string(),
+        };
+        fields.join(&sep)
+    }
+
+    /// Gets the scope in which a variable is set: the innermost function that
+    /// declared it local, or else the global variables.
+    crate fn scope_mut(&mut self, name: &str) -> &mut BTreeMap<String, Var> {
+        match self.locals.iter().rposition(|frame| frame.contains_key(name)) {
+            Some(n) => &mut self.locals[n],
+            None => &mut self.vars,
+        }
+    }
+
+    /// Gets the value of a shell variable to change it, creating the variable
+    /// if need be. This is the innermost local variable of that name if there
+    /// is one.
+    pub fn value_mut(&mut self, name: &str) -> Result<&mut Option<Value>, ReadOnly> {
+        self.value_mut_in(name, false)
+    }
+
+    /// Gets the value of a shell variable to change it, as `value_mut` does,
+    /// or the value of the global variable when `global` is set, even if a
+    /// local variable of that name hides it.
+    crate fn value_mut_in(&mut self, name: &str, global: bool) -> Result<&mut Option<Value>, ReadOnly> {
+        let scope = if global { &mut self.vars } else { self.scope_mut(name) };
+        let var = scope.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(ReadOnly(name.to_string()));
+        }
+        Ok(&mut var.value)
+    }
+
+    /// Sets a shell variable, which is the innermost local variable of that
+    /// name if there is one. Setting an array sets its element `0`.
+    pub fn set_var(&mut self, name: &str, value: String) -> Result<(), ReadOnly> {
+        match self.value_mut(name)? {
+            Some(array) => array.set_element(Subscript::Index(0), value),
+            slot => *slot = Some(Value::Scalar(value)),
+        }
+        Ok(())
+    }
+
+    /// Unsets a shell variable. A local variable stays local, but with no
+    /// value or attributes, so that setting it again does not reach a variable
+    /// outside the function.
+    pub fn unset_var(&mut self, name: &str) -> Result<(), ReadOnly> {
+        let local = self.locals.iter().rposition(|frame| frame.contains_key(name));
+        let scope = match local {
+            Some(n) => &mut self.locals[n],
+            None => &mut self.vars,
+        };
+        if scope.get(name).map_or(false, |var| var.readonly) {
+            return Err(ReadOnly(name.to_string()));
+        }
+        match local {
+            Some(_) => scope.insert(name.to_string(), Var::default()),
+            None => scope.remove(name),
+        };
+        Ok(())
+    }
+
+    /// Sets and exports a global variable that the shell maintains itself,
+    /// such as `$PWD`, whatever its attributes.
+    fn put(&mut self, name: &str, value: String) {
+        let var = self.vars.entry(name.to_string()).or_default();
+        var.value = Some(Value::Scalar(value));
+        var.exported = true;
+    }
+
+    /// Builds the environment of a program the shell runs, from the exported
+    /// variables that are set. Arrays cannot be exported.
+    pub fn environment(&self) -> BTreeMap<&str, &str> {
+        let mut env = BTreeMap::new();
+        let scopes = Some(&self.vars).into_iter().chain(&self.locals);
+        for (name, var) in scopes.flat_map(|scope| scope.iter()) {
+            match var.value {
+                Some(Value::Scalar(ref value)) if var.exported => env.insert(name.as_str(), value.as_str()),
+                //  An inner declaration hides the outer variable, even if it
+                //  is not exported itself.
+                _ => env.remove(name.as_str()),
+            };
+        }
+        env
+    }
+
+    /// Gets the value of a parameter: a variable, a positional parameter such
+    /// as `1`, the name `0`, or one of the special parameters.
+    ///
+    /// - `#`: the number of positional parameters
+    /// - `@`: the positional parameters, joined by spaces
+    /// - `*`: the positional parameters, joined by the first character of
+    ///   `$IFS`, or by nothing if it is empty
+    /// - `?`: the exit status of the most recent command
+    /// - `$`: the process ID of the shell, which subshells share
+    /// - `!`: the process ID of the most recent background job
+    /// - `-`: the flags of the shell, `i` if it is interactive
+    pub fn param(&self, name: &str) -> Option<String> {
+        match name {
+            "#" => Some(self.args.len().to_string()),
+            "@" => Some(self.args.join(" ")),
+            "*" => Some(self.join_fields(&self.args)),
+            "?" => Some(self.status.to_string()),
+            "$" => Some(std::process::id().to_string()),
+            //  ysh does not run background jobs yet, so there is never one.
+            "!" => None,
+            "-" => Some(if self.interactive { "i" } else { "" }.to_string()),
+            "0" => Some(self.name.clone()),
+            n if n.starts_with(|c: char| c.is_ascii_digit()) => {
+                let n = n.parse::<usize>().ok()?;
+                self.args.get(n.checked_sub(1)?).cloned()
+            },
+            name => self.var(name),
+        }
+    }
+}
+
+impl arith::Variables for State {
+    fn get(&self, name: &str) -> Option<String> {
+        self.var(name)
+    }
+
+    fn set(&mut self, name: &str, value: String) -> Result<(), arith::ArithError> {
+        self.set_var(name, value).map_err(|e| arith::ArithError::ReadOnly(e.0))
+    }
+}
+
+/// Lexically removes `.` and `..` components from an absolute path.
+///
+/// Unlike `canonicalize`, this does not consult the filesystem, so a symbolic
+/// link in the path stays as it was written: `..` after a link leads back to
+/// the directory holding the link, not to the parent of its target.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                out.pop();
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Converts a child's exit status into the number reported by `$?`.
+///
+/// Children killed by a signal report 128 plus the signal number, as in other
+/// shells.
+#[cfg(target_family = "unix")]
+pub fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status.code()
+        .or_else(|| status.signal().map(|s| 128 + s))
+        .unwrap_or(1)
+}
+
+#[cfg(target_family = "windows")]
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
+/// Reports whether a child was killed by the SIGINT that Ctrl-C sends.
+#[cfg(target_family = "unix")]
+pub fn interrupted(status: ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(libc::SIGINT)
+}
+
+#[cfg(target_family = "windows")]
+pub fn interrupted(_status: ExitStatus) -> bool {
+    false
+}
+
+/// Set by the SIGINT handler of the shell, for `take_interrupt`.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a SIGINT handler for the shell process, which only records the
+/// signal, so that the shell survives it and stops what it is running between
+/// commands.
+///
+/// A handler is used rather than `SIG_IGN` because ignored signals stay ignored
+/// across `exec`, which would make every child immune to Ctrl-C. Caught signals
+/// are reset to their default action in the child, so jobs still terminate.
+#[cfg(target_family = "unix")]
+pub fn catch_interrupt() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as libc::sighandler_t);
+    }
+}
+
+/// Windows delivers Ctrl-C to console processes as a control event, which is
+/// not raised while the console is in raw mode.
+#[cfg(target_family = "windows")]
+pub fn catch_interrupt() {}
+
+/// Reports whether Ctrl-C has been pressed since the last time this was
+/// asked.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
 /// Gets the hostname of the machine running the shell.
 #[cfg(target_family = "unix")]
 pub fn hostname() -> Result<String, Error> {
@@ -225,6 +1073,32 @@ pub fn user() -> Result<String, Error> {
         .or_else(|_| bail!("Username is not UTF-8"))
 }
 
+/// Gets the home directory of the named user from the system registry.
+#[cfg(target_family = "unix")]
+pub fn home_of(name: &str) -> Option<PathBuf> {
+    use std::{ffi::{CStr, CString, OsStr}, os::unix::ffi::OsStrExt};
+    let name = CString::new(name).ok()?;
+    //  Like `getpwuid`, this returns a pointer into thread-local storage that
+    //  is overwritten by the next call, so the directory is copied out
+    //  immediately.
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return None;
+    }
+    let dir = unsafe { (*passwd).pw_dir };
+    if dir.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr(dir) };
+    Some(PathBuf::from(OsStr::from_bytes(dir.to_bytes())))
+}
+
+/// Windows has no registry of users' home directories to consult.
+#[cfg(target_family = "windows")]
+pub fn home_of(_name: &str) -> Option<PathBuf> {
+    None
+}
+
 #[cfg(target_family = "windows")]
 pub fn hostname() -> Result<String, Error> {
     // We want the Max length for NetBIOS names which is 15 chars
@@ -6,24 +6,83 @@ use crossterm::{
     terminal::{self, ClearType},
 };
 use std::fmt;
-use std::io::Write;
+use std::io::{self, Write};
 use std::str;
-use crate::st;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::{
+    prompt::{self, Prompt},
+    st,
+};
+
+/// Whether the terminal is in raw mode, where only `\r` returns the cursor to
+/// the first column. Like the terminal, this is global to the process.
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// Records whether the shell has put the terminal in raw mode. Without raw
+/// mode, as when running a script, a newline is written plainly.
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+/// Reports whether the shell's standard input is a terminal. When it is not,
+/// as when commands are piped in, the shell reads them without prompting.
+#[cfg(unix)]
+pub fn stdin_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+#[cfg(windows)]
+pub fn stdin_is_tty() -> bool {
+    true
+}
+
+/// The shell's standard input, read without a buffer, so that the shell takes
+/// no more of it than it asks for. What it leaves is there for the programs it
+/// runs to read.
+pub struct Unbuffered;
+
+#[cfg(unix)]
+impl io::Read for Unbuffered {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe {
+            libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+#[cfg(windows)]
+impl io::Read for Unbuffered {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(&mut io::stdin(), buf)
+    }
+}
 
 pub trait Term: Write + Sized {
     fn cursor(&self) -> cursor::TerminalCursor;
     fn terminal(&self) -> terminal::Terminal;
 
     fn reset(&mut self, state: &st::State) -> Result<(), Error> {
+        self.clear()?;
+        self.prompt(state)
+    }
+
+    /// Clears the screen, and moves the cursor to the top left corner.
+    fn clear(&mut self) -> Result<(), Error> {
         let cursor = self.cursor();
         let term = self.terminal();
         term.clear(ClearType::All);
         cursor.goto(0,0);
-        self.prompt(state)
+        Ok(())
     }
 
     fn newline(&mut self) -> Result<(), Error> {
-        self.write(b"\r\n")?;
+        let newline: &[u8] = if RAW.load(Ordering::Relaxed) { b"\r\n" } else { b"\n" };
+        self.write(newline)?;
         self.flush()?;
         Ok(())
     }
@@ -72,11 +131,37 @@ pub trait Term: Write + Sized {
     }
 
     fn prompt(&mut self, state: &st::State) -> Result<(), Error> {
-        write!(self, "{user}@{host}:{pwd} % ",
-            user = state.user,
-            host = state.host,
-            pwd = state.pwd.display()
-        )?;
+        let left = Prompt::new(&prompt::template(state, "PS1", prompt::DEFAULT_PS1))
+            .render(state);
+        self.write(left.text.as_bytes())?;
+
+        //  The right-hand prompt is drawn flush against the right edge of the
+        //  terminal, and the cursor returned to the end of the left prompt. It
+        //  is left out entirely when the two would collide.
+        let right = prompt::template(state, "RPROMPT", "");
+        if !right.is_empty() {
+            let right = Prompt::new(&right).render(state);
+            let (cols, _) = self.terminal().terminal_size();
+            let cols = cols as usize;
+            if left.width + right.width < cols {
+                let cursor = self.cursor();
+                let (_, row) = cursor.pos();
+                cursor.save_position();
+                cursor.goto((cols - right.width) as u16, row);
+                self.write(right.text.as_bytes())?;
+                cursor.reset_position();
+            }
+        }
+
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Prints the continuation prompt, for input that spans several lines.
+    fn continuation(&mut self, state: &st::State) -> Result<(), Error> {
+        let ps2 = Prompt::new(&prompt::template(state, "PS2", prompt::DEFAULT_PS2))
+            .render(state);
+        self.write(ps2.text.as_bytes())?;
         self.flush()?;
         Ok(())
     }
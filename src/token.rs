@@ -123,6 +123,35 @@ pub fn atom(text: &str) -> TokenResult {
     alt!(text, shell_meta | dquote | squote | word)
 }
 
+/// Reports whether the text stops partway through a token.
+///
+/// This is the case for unterminated quoted strings and shell meta-sequences,
+/// and for here-documents that have not reached their delimiter line.
+/// Interactive frontends use this to decide whether to submit the input or to
+/// keep reading further lines into it.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::incomplete;
+///
+/// assert!(!incomplete("echo 'hello world'"));
+/// assert!(incomplete("echo 'hello"));
+/// assert!(incomplete("echo $(ls"));
+/// assert!(incomplete("cat <<EOF\nhello"));
+/// assert!(!incomplete("cat <<EOF\nhello\nEOF"));
+/// ```
+pub fn incomplete(text: &str) -> bool {
+    use nom::Err;
+    //  Only the first line is the command. Any lines after it are the bodies
+    //  of its here-documents.
+    match line(text) {
+        Ok(_) => here_pending(text),
+        Err(Err::Incomplete(_)) => true,
+        Err(_) => false,
+    }
+}
+
 /// Finds a bare word.
 ///
 /// A word is defined as any run of non-whitespace characters. This tokenizer
@@ -160,6 +189,545 @@ pub fn word(text: &str) -> TokenResult {
         })
 }
 
+/// Finds a complete shell word, with its quoting intact.
+///
+/// A shell word is a run of text up to the next unquoted whitespace. Unlike
+/// `atom`, which produces one token at a time and strips its quotes, this keeps
+/// adjacent tokens together and returns the word exactly as written, so that
+/// `foo"bar baz"'*'` is a single word. Later phases of the shell need the
+/// quotes to know which characters were quoted.
+///
+/// Quoted strings and shell meta-sequences inside the word may contain
+/// whitespace; a backslash makes the following character part of the word.
+/// An unquoted here-document or here-string operator also ends the word, so
+/// that `cat<<EOF` is the word `cat` followed by the operator.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::shell_word;
+///
+/// let (rest, word) = shell_word(r#"--name="A User"'s tool' next"#)
+///     .expect("quotes and bare text join into one word");
+/// assert_eq!(word, r#"--name="A User"'s tool'"#);
+/// assert_eq!(rest, " next");
+///
+/// let (rest, word) = shell_word("file<<EOF").unwrap();
+/// assert_eq!(word, "file");
+/// assert_eq!(rest, "<<EOF");
+/// ```
+pub fn shell_word(text: &str) -> TokenResult {
+    use nom::{Context, Err, ErrorKind};
+    let mut rem = text;
+    loop {
+        rem = until(rem, |c, _| c.is_whitespace() || c == '<')?.0;
+        if !rem.starts_with('<') || rem.starts_with("<<") {
+            break;
+        }
+        //  Any other `<` belongs to the word, as does a process
+        //  substitution.
+        rem = if rem[1 ..].starts_with('(') { process(rem)?.0 } else { &rem[1 ..] };
+    }
+    let word = &text[.. text.len() - rem.len()];
+    if word.is_empty() {
+        return Err(Err::Error(Context::Code(text, ErrorKind::Custom(0))));
+    }
+    Ok((rem, word))
+}
+
+/// Advances through the text until `stop` accepts a character that is not
+/// quoted, escaped, or part of a shell meta-sequence.
+///
+/// `stop` also learns whether the character begins a word, which is to say
+/// that it comes first or follows whitespace. This produces the text before
+/// the stopping character, which may be empty, and leaves the stopping
+/// character in the remaining text.
+fn until(text: &str, stop: impl Fn(char, bool) -> bool) -> TokenResult {
+    let mut rem = text;
+    let mut start = true;
+    let mut prev = None;
+    while let Some(c) = rem.chars().next() {
+        if stop(c, start) {
+            break;
+        }
+        start = c.is_whitespace();
+        let after_equals = prev == Some('=');
+        prev = Some(c);
+        rem = match c {
+            '\\' => {
+                let mut chars = rem.chars();
+                chars.next();
+                chars.next();
+                chars.as_str()
+            },
+            '\'' => squote(rem)?.0,
+            '"' => dquote(rem)?.0,
+            //  Braced and parenthesized meta-sequences may contain whitespace.
+            //  Bare ones, like `$name`, are ordinary word characters.
+            '$' if rem[1 ..].starts_with('(') || rem[1 ..].starts_with('{') => {
+                shell_meta(rem)?.0
+            },
+            '<' | '>' if rem[1 ..].starts_with('(') => process(rem)?.0,
+            //  The list assigned to an array, as in `arr=(a b)`, is part of
+            //  the word.
+            '(' if after_equals => list(rem)?.0,
+            c => &rem[c.len_utf8() ..],
+        };
+    }
+    Ok((rem, &text[.. text.len() - rem.len()]))
+}
+
+/// Finds a comment.
+///
+/// A comment begins with `#` and runs to the end of the line. This produces
+/// the comment text after the `#`, and leaves the newline, if any, in the
+/// remaining text. Callers decide where a comment may begin: in the shell
+/// language, only an unquoted `#` at the start of a word begins a comment, so
+/// that `a#b` is a single word.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::comment;
+///
+/// let (rest, text) = comment("# list files\nls")
+///     .expect("comments run to the end of the line");
+/// assert_eq!(text, " list files");
+/// assert_eq!(rest, "\nls");
+/// ```
+pub fn comment(text: &str) -> TokenResult {
+    use nom::tag;
+    let (body, _) = tag!(text, "#")?;
+    let len = body.find('\n').unwrap_or(body.len());
+    Ok((&body[len ..], &body[.. len]))
+}
+
+/// Finds a logical line of input.
+///
+/// This produces the text up to the first newline that is not inside a quoted
+/// string or shell meta-sequence, and consumes the newline. A backslash before
+/// a newline joins the two lines. With no newline, the whole text is the line.
+/// A comment on the line is not part of it.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::line;
+///
+/// let (rest, first) = line("echo 'two\nlines'\nnext")
+///     .expect("quoted newlines do not end the line");
+/// assert_eq!(first, "echo 'two\nlines'");
+/// assert_eq!(rest, "next");
+///
+/// let (rest, first) = line("echo a#b # it's a comment\nnext")
+///     .expect("comments begin at the start of a word");
+/// assert_eq!(first, "echo a#b ");
+/// assert_eq!(rest, "next");
+/// ```
+pub fn line(text: &str) -> TokenResult {
+    let (rem, line) = until(text, |c, start| c == '\n' || (c == '#' && start))?;
+    let rem = match comment(rem) {
+        Ok((rest, _)) => rest,
+        Err(_) => rem,
+    };
+    Ok((rem.get(1 ..).unwrap_or(""), line))
+}
+
+/// Finds a simple command.
+///
+/// A simple command runs up to the first unquoted `;` or newline, or to a
+/// comment. The separator, or the comment, is left in the remaining text.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::command;
+///
+/// let (rest, cmd) = command("echo 'a;b' c; next")
+///     .expect("quoted separators do not end the command");
+/// assert_eq!(cmd, "echo 'a;b' c");
+/// assert_eq!(rest, "; next");
+///
+/// let (rest, cmd) = command("ls # list\nnext").unwrap();
+/// assert_eq!(cmd, "ls ");
+/// assert_eq!(rest, "# list\nnext");
+/// ```
+pub fn command(text: &str) -> TokenResult {
+    until(text, |c, start| c == ';' || c == '\n' || (c == '#' && start))
+}
+
+/// Finds a simple command inside a subshell, where an unquoted `)` also ends
+/// the command.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::subshell_command;
+///
+/// let (rest, cmd) = subshell_command("echo ')' $(pwd)) | next")
+///     .expect("quoted and nested parentheses do not end the command");
+/// assert_eq!(cmd, "echo ')' $(pwd)");
+/// assert_eq!(rest, ") | next");
+/// ```
+pub fn subshell_command(text: &str) -> TokenResult {
+    until(text, |c, start| c == ';' || c == '\n' || c == ')' || (c == '#' && start))
+}
+
+/// Finds a pattern in a `case` command.
+///
+/// A pattern runs up to the `|` that separates it from the next pattern, the
+/// `)` that ends the list of patterns, or whitespace.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::pattern;
+///
+/// let (rest, pat) = pattern("*.rs|'a|b') cmd")
+///     .expect("patterns end at an unquoted bar");
+/// assert_eq!(pat, "*.rs");
+/// assert_eq!(rest, "|'a|b') cmd");
+/// ```
+pub fn pattern(text: &str) -> TokenResult {
+    until(text, |c, _| c == '|' || c == ')' || c.is_whitespace())
+}
+
+/// Finds a here-document or here-string operator, and the word after it.
+///
+/// The operators are `<<` for a here-document, `<<-` for a here-document with
+/// its leading tabs removed, and `<<<` for a here-string. Whitespace may come
+/// between the operator and its word. The word is returned as written, with
+/// its quotes.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::here_op;
+///
+/// let (rest, (op, word)) = here_op("<<- 'EOF' > out")
+///     .expect("a here-document operator takes a word");
+/// assert_eq!(op, "<<-");
+/// assert_eq!(word, "'EOF'");
+/// assert_eq!(rest, " > out");
+///
+/// assert!(here_op("<<").is_err());
+/// ```
+pub fn here_op(text: &str) -> TokenResult<(&str, &str)> {
+    use nom::{Context, Err, ErrorKind};
+    let op = ["<<<", "<<-", "<<"].iter()
+        .find(|op| text.starts_with(*op))
+        .ok_or_else(|| Err::Error(Context::Code(text, ErrorKind::Custom(0))))?;
+    let (rem, word) = trim_left(shell_word)(&text[op.len() ..])?;
+    Ok((rem, (&text[.. op.len()], word)))
+}
+
+/// Finds the body of a here-document.
+///
+/// The body is every line of the text up to a line consisting only of the
+/// delimiter `word`, which is written as it was given to the here-document
+/// operator: its quotes are removed before comparing. When `strip` is set, as
+/// for `<<-`, leading tabs are ignored on the delimiter line.
+///
+/// The body is returned as written, including its final newline, and the text
+/// after the delimiter line remains. If no delimiter line is found, the body is
+/// incomplete.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::here_body;
+///
+/// let (rest, body) = here_body("one\ntwo\n\tEOF\nnext", "'EOF'", true)
+///     .expect("the delimiter ends the body");
+/// assert_eq!(body, "one\ntwo\n");
+/// assert_eq!(rest, "next");
+///
+/// assert!(here_body("one\nEOFX\n", "EOF", false).is_err());
+/// ```
+pub fn here_body<'a>(text: &'a str, word: &str, strip: bool) -> TokenResult<'a> {
+    use nom::{Err, Needed};
+    let delimiter = word.chars()
+        .filter(|c| !"'\"\\".contains(*c))
+        .collect::<String>();
+    let mut start = 0;
+    while start < text.len() {
+        let end = text[start ..].find('\n').map_or(text.len(), |n| start + n);
+        let mut line = &text[start .. end];
+        if strip {
+            line = line.trim_start_matches('\t');
+        }
+        if line == delimiter {
+            let rest = if end < text.len() { &text[end + 1 ..] } else { "" };
+            return Ok((rest, &text[.. start]));
+        }
+        start = end + 1;
+    }
+    Err(Err::Incomplete(Needed::Unknown))
+}
+
+/// Finds the bodies of all the here-documents in a command.
+///
+/// `command` is one line holding any number of here-document operators, and
+/// the text holds the lines after it. This produces the lines holding the
+/// bodies, one after another in the order of their operators, including the
+/// delimiter lines. A command without here-documents has no bodies, and
+/// neither has an arithmetic command, whose `<<` is a shift.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::here_bodies;
+///
+/// let (rest, bodies) = here_bodies("cat <<A - <<-B", "a\nA\n\tb\n\tB\nnext")
+///     .expect("each body ends at its delimiter");
+/// assert_eq!(bodies, "a\nA\n\tb\n\tB\n");
+/// assert_eq!(rest, "next");
+///
+/// assert_eq!(here_bodies("cat<<A", "a\nA\n"), Ok(("", "a\nA\n")));
+/// assert_eq!(here_bodies("echo <<<word", "next"), Ok(("next", "")));
+/// assert_eq!(here_bodies("(( x <<= 1 )); echo", "next"), Ok(("next", "")));
+/// ```
+pub fn here_bodies<'a>(command: &str, text: &'a str) -> TokenResult<'a> {
+    let mut body = text;
+    let mut rem = command;
+    while !rem.trim().is_empty() {
+        rem = match trim_left(here_op)(rem) {
+            Ok((rest, (op, word))) => {
+                if op != "<<<" {
+                    body = here_body(body, word, op == "<<-")?.0;
+                }
+                rest
+            },
+            Err(_) => match trim_left(command_word)(rem) {
+                Ok((rest, _)) => rest,
+                Err(_) => break,
+            },
+        };
+    }
+    Ok((body, &text[.. text.len() - body.len()]))
+}
+
+/// Finds the next word of a command line, as `shell_word` does, except that an
+/// arithmetic command is one word, so that its `<<` is not taken for a
+/// here-document.
+crate fn command_word(text: &str) -> TokenResult {
+    match arith(text) {
+        Ok((rest, _)) => Ok((rest, &text[.. text.len() - rest.len()])),
+        Err(_) => shell_word(text),
+    }
+}
+
+/// Reports whether the first line of a text has here-documents whose bodies
+/// have not all been entered.
+fn here_pending(text: &str) -> bool {
+    match line(text) {
+        Ok((rest, first)) => here_bodies(first, rest).is_err(),
+        Err(_) => false,
+    }
+}
+
+/// Finds a brace-delimited group, as used by brace expansion.
+///
+/// If the text begins with `{`, this produces the text up to the matching `}`,
+/// not including either brace. Braces may nest. Quoted strings, backslash
+/// escapes, and shell meta-sequences inside the group are skipped whole, so
+/// their braces do not count: in particular, the `{` of a `${name}` variable
+/// expansion never opens or closes a group.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::brace;
+///
+/// let (rest, body) = brace("{a,{b,c},${d}}e")
+///     .expect("brace groups nest, and skip variable expansions");
+/// assert_eq!(body, "a,{b,c},${d}");
+/// assert_eq!(rest, "e");
+///
+/// //  The `$` belongs to a meta-sequence, not to a brace group
+/// assert!(brace("${var}").is_err());
+/// ```
+pub fn brace(text: &str) -> TokenResult {
+    use nom::{tag, Err, Needed};
+    let (body, _) = tag!(text, "{")?;
+    let mut depth = 0;
+    let mut rem = body;
+    while let Some(c) = rem.chars().next() {
+        rem = match c {
+            '{' => {
+                depth += 1;
+                &rem[1 ..]
+            },
+            '}' if depth == 0 => {
+                let len = body.len() - rem.len();
+                return Ok((&rem[1 ..], &body[.. len]));
+            },
+            '}' => {
+                depth -= 1;
+                &rem[1 ..]
+            },
+            '\\' => {
+                let mut chars = rem.chars();
+                chars.next();
+                chars.next();
+                chars.as_str()
+            },
+            '\'' => squote(rem)?.0,
+            '"' => dquote(rem)?.0,
+            '$' if rem[1 ..].starts_with('(') || rem[1 ..].starts_with('{') => {
+                shell_meta(rem)?.0
+            },
+            c => &rem[c.len_utf8() ..],
+        };
+    }
+    Err(Err::Incomplete(Needed::Size(1)))
+}
+
+/// Finds a process substitution, `<(command)` or `>(command)`.
+///
+/// This produces the direction, `<` or `>`, and the command between the
+/// parentheses. Like the other meta-sequences, the command may contain quotes,
+/// nested parentheses, and other meta-sequences, which are skipped whole.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::process;
+///
+/// let (rest, (op, cmd)) = process("<(sort 'a)' $(ls)) b")
+///     .expect("quoted parentheses do not end the command");
+/// assert_eq!(op, "<");
+/// assert_eq!(cmd, "sort 'a)' $(ls)");
+/// assert_eq!(rest, " b");
+///
+/// assert!(process("(ls)").is_err());
+/// ```
+pub fn process(text: &str) -> TokenResult<(&str, &str)> {
+    use nom::{Context, Err, ErrorKind, Needed};
+    if !text.starts_with('<') && !text.starts_with('>') {
+        return Err(Err::Error(Context::Code(text, ErrorKind::Custom(0))));
+    }
+    match text[1 ..].chars().next() {
+        Some('(') => {},
+        Some(_) => return Err(Err::Error(Context::Code(text, ErrorKind::Custom(0)))),
+        None => return Err(Err::Incomplete(Needed::Size(2))),
+    }
+    let body = &text[2 ..];
+    let mut depth = 0;
+    let mut rem = body;
+    while let Some(c) = rem.chars().next() {
+        rem = match c {
+            '\\' => {
+                let mut chars = rem.chars();
+                chars.next();
+                chars.next();
+                chars.as_str()
+            },
+            '\'' => squote(rem)?.0,
+            '"' => dquote(rem)?.0,
+            '$' if rem[1 ..].starts_with('(') || rem[1 ..].starts_with('{') => {
+                shell_meta(rem)?.0
+            },
+            '(' => {
+                depth += 1;
+                &rem[1 ..]
+            },
+            ')' if depth == 0 => {
+                let len = body.len() - rem.len();
+                return Ok((&rem[1 ..], (&text[.. 1], &body[.. len])));
+            },
+            ')' => {
+                depth -= 1;
+                &rem[1 ..]
+            },
+            c => &rem[c.len_utf8() ..],
+        };
+    }
+    Err(Err::Incomplete(Needed::Size(1)))
+}
+
+/// Finds a parenthesized list of words, as in the array assignment
+/// `arr=(a b c)`.
+///
+/// This produces the words between the parentheses, as they were written.
+/// Parentheses that are quoted or inside meta-sequences do not end the list.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::list;
+///
+/// let (rest, words) = list("(a 'b)' $(c)) d")
+///     .expect("quoted parentheses do not end the list");
+/// assert_eq!(words, "a 'b)' $(c)");
+/// assert_eq!(rest, " d");
+///
+/// assert!(list("a b").is_err());
+/// ```
+pub fn list(text: &str) -> TokenResult {
+    use nom::{tag, Err, Needed};
+    let (body, _) = tag!(text, "(")?;
+    let (rem, words) = until(body, |c, _| c == ')')?;
+    if rem.is_empty() {
+        return Err(Err::Incomplete(Needed::Size(1)));
+    }
+    Ok((&rem[1 ..], words))
+}
+
+/// Finds an arithmetic sequence, `((expression))`.
+///
+/// This produces the expression between the doubled parentheses. Parentheses
+/// inside the expression must balance, and shell meta-sequences inside it are
+/// skipped whole. Text such as `((a) (b))`, where the first inner parenthesis
+/// closes before the outer pair does, is not arithmetic: it is a subshell that
+/// happens to begin with a parenthesized group.
+///
+/// This is used both for `$((expression))` expansions and for the
+/// `((expression))` command.
+///
+/// # Usage
+///
+/// ```rust
+/// use ysh::token::arith;
+///
+/// let (rest, expr) = arith("((x * (y + 1))) && next")
+///     .expect("inner parentheses balance");
+/// assert_eq!(expr, "x * (y + 1)");
+/// assert_eq!(rest, " && next");
+///
+/// assert!(arith("((a) (b))").is_err());
+/// ```
+pub fn arith(text: &str) -> TokenResult {
+    use nom::{tag, Context, Err, ErrorKind, Needed};
+    let (body, _) = tag!(text, "((")?;
+    let mut depth = 0;
+    let mut rem = body;
+    while let Some(c) = rem.chars().next() {
+        rem = match c {
+            '(' => {
+                depth += 1;
+                &rem[1 ..]
+            },
+            ')' if depth == 0 => {
+                if !rem[1 ..].starts_with(')') {
+                    return Err(Err::Error(Context::Code(text, ErrorKind::Custom(0))));
+                }
+                let len = body.len() - rem.len();
+                return Ok((&rem[2 ..], &body[.. len]));
+            },
+            ')' => {
+                depth -= 1;
+                &rem[1 ..]
+            },
+            '$' if rem[1 ..].starts_with('(') || rem[1 ..].starts_with('{') => {
+                shell_meta(rem)?.0
+            },
+            c => &rem[c.len_utf8() ..],
+        };
+    }
+    Err(Err::Incomplete(Needed::Size(2)))
+}
+
 /// Finds a single-quote-delimited string.
 ///
 /// This tokenizer is the regex `/'([^']*)'/`. If the text it is given begins
@@ -217,6 +785,11 @@ pub fn squote(text: &str) -> TokenResult {
 ///     .expect("double-quoted strings are tokenized with difficulty");
 /// assert_eq!(dq, r#"dquotes \"may nest\" and $(even "nest shells")"#);
 /// assert_eq!(rest, "excluded");
+///
+/// let (rest, dq) = dquote(r#""$name" next"#)
+///     .expect("a bare parameter ends at the quote");
+/// assert_eq!(dq, "$name");
+/// assert_eq!(rest, " next");
 /// ```
 pub fn dquote(text: &str) -> TokenResult {
     use nom::Err;
@@ -239,13 +812,18 @@ pub fn dquote(text: &str) -> TokenResult {
             //  A backslash (U+005C) skips the next character.
             //  TODO(myrrlyn): Make a backslash processor
             '\\' => drop(iter.next()),
-            //  An unescaped dollar sign (U+0024) begins a shell meta-sequence.
+            //  An unescaped dollar sign (U+0024) and a bracket begin a shell
+            //  meta-sequence.
             //  - process the entire sequence, `shell_meta(...)?`
             //  - take the shell sequence, `.1`
             //  - iterate over its characters, `.chars()`
             //  - advance the main iterator for each of them, `.for_each(...)`
-            '$' => shell_meta(&text[i ..])?.1.chars()
-                .for_each(|_| drop(iter.next())),
+            //  A bare meta-sequence, like `$name`, is ordinary text here, and
+            //  must not run on past the closing quote.
+            '$' if text[i + 1 ..].starts_with('(') || text[i + 1 ..].starts_with('{') => {
+                shell_meta(&text[i ..])?.1.chars()
+                    .for_each(|_| drop(iter.next()))
+            },
             //  ALl other characters are uninteresting
             _ => continue,
         }
@@ -256,23 +834,34 @@ pub fn dquote(text: &str) -> TokenResult {
     Err(Err::Incomplete(Needed::Size(1)))
 }
 
-/// Finds a `key=value` sequence and splits it into the key and the value.
-///
-/// This tokenizer uses `word` on the left side of the equals sign and `atom` on
-/// the right. It rejects any whitespace between the key, the equals sign, and
+/// Finds a `key=value` sequence and splits it into the key, the operator, and
 /// the value.
 ///
+/// This tokenizer uses `word` on the left side of the operator and `atom` on
+/// the right. It rejects any whitespace between the key, the operator, and the
+/// value. The operator is `=`, or `+=` to append the value to the key's.
+///
+/// The value may also be a parenthesized list, as in `arr=(a b c)`, which is
+/// produced whole, with its parentheses (see `list`).
+///
 /// # Usage
 ///
 /// ```rust
 /// use ysh::token::keyval;
 ///
-/// let (_, (key, value)) = keyval("hello=\"dear reader\"")
+/// let (_, (key, op, value)) = keyval("hello=\"dear reader\"")
 ///     .expect("key/value pairs can have any atom as their value");
 /// assert_eq!(key, "hello");
+/// assert_eq!(op, "=");
 /// assert_eq!(value, "dear reader");
+///
+/// let (_, (key, op, value)) = keyval("words+=(a 'b c')")
+///     .expect("key/value pairs can have a list as their value");
+/// assert_eq!(key, "words");
+/// assert_eq!(op, "+=");
+/// assert_eq!(value, "(a 'b c')");
 /// ```
-pub fn keyval(text: &str) -> TokenResult<(&str, &str)> {
+pub fn keyval(text: &str) -> TokenResult<(&str, &str, &str)> {
     use nom::tag;
     use nom::take_until1;
     //  TODO(myrrlyn): Patch nom to not leak error_position from take_until1
@@ -284,11 +873,21 @@ pub fn keyval(text: &str) -> TokenResult<(&str, &str)> {
         .and_then(|(_, t)| word(t))
         //  The remnant is now everything after `word`'s success value.
         .map(|(_, w)| (&text[w.len() ..], w))?;
-    //  The next character after `word` **must** be `=`.
+    //  The next character after `word` **must** be `=`. A `+` before it is
+    //  part of the operator, not the key.
     let (rem, _) = tag!(rem, "=")?;
-    //  Take the next atom.
+    let (key, op) = if key.len() > 1 && key.ends_with('+') {
+        (&key[.. key.len() - 1], &text[key.len() - 1 .. key.len() + 1])
+    } else {
+        (key, &text[key.len() .. key.len() + 1])
+    };
+    //  Take the next list or atom.
+    if rem.starts_with('(') {
+        let (after, _) = list(rem)?;
+        return Ok((after, (key, op, &rem[.. rem.len() - after.len()])));
+    }
     let (rem, val) = atom(rem)?;
-    Ok((rem, (key, val)))
+    Ok((rem, (key, op, val)))
 }
 
 /// Finds a shell meta-sequence.
@@ -296,6 +895,8 @@ pub fn keyval(text: &str) -> TokenResult<(&str, &str)> {
 /// A shell meta-sequence begins with a dollar sign character, `$` (U+0024), and
 /// is followed by one of:
 ///
+/// - a doubled-parentheses sequence, `((text))`, indicating an arithmetic
+///   expansion (see `arith`)
 /// - a parentheses-enclesed sequence, `(text)`, indicating a subshell command
 /// - a brace-enclosed sequence, `{text}`, indicating a variable expansion
 /// - a bare word, `text`, indicating a variable expansion
@@ -348,11 +949,27 @@ pub fn keyval(text: &str) -> TokenResult<(&str, &str)> {
 ///     .expect("subshells can have inner subshells or other constructs");
 /// assert_eq!(shell, "(cmd $(inner))");
 /// ```
+///
+/// Find an arithmetic expansion, like `$((1 + 2))`.
+///
+/// ```rust
+/// # use ysh::token::shell_meta;
+/// let (_, expr) = shell_meta("$(( (1 + 2) * 3 ))")
+///     .expect("arithmetic is a meta-sequence");
+/// assert_eq!(expr, "(( (1 + 2) * 3 ))");
+/// ```
 pub fn shell_meta(text: &str) -> TokenResult {
     use nom::tag;
     use nom::Err;
     use nom::Needed;
     let (text, _) = tag!(text, "$")?;
+    //  Arithmetic takes precedence over a subshell, which it can only be
+    //  mistaken for when the subshell begins with a parenthesized group.
+    match arith(text) {
+        Ok((rest, _)) => return Ok((rest, &text[.. text.len() - rest.len()])),
+        Err(Err::Incomplete(n)) => return Err(Err::Incomplete(n)),
+        Err(_) => {},
+    }
     let close = match text.clone().chars().next() {
         Some('(') => ')',
         Some('{') => '}',
@@ -373,12 +990,20 @@ pub fn shell_meta(text: &str) -> TokenResult {
         }
         //  If any of these bulk tokenizers match on the text, fast-forward
         //  through them.
-        for tokenizer in &[dquote, squote, shell_meta] {
+        for tokenizer in &[dquote, squote] {
             if let Ok((rest, _)) = trim_left(tokenizer)(rem) {
                 rem = rest;
                 continue 'outer;
             }
         }
+        //  An inner `$name` is scanned like any other text, because its bare
+        //  word would run on past the closer.
+        if rem.starts_with("$(") || rem.starts_with("${") {
+            if let Ok((rest, _)) = shell_meta(rem) {
+                rem = rest;
+                continue;
+            }
+        }
         //  Otherwise inspect the next character
         match rem.clone().chars().next() {
             //  If it's the matching closer to the opener found above, return
@@ -485,15 +1110,47 @@ mod tests {
             .expect("shell_meta must succeed");
 
         assert_eq!(s, "(cmd \"inner string\")");
+
+        let (r, v) = shell_meta("${a#$b}${c/${d}/$(e)} f").expect("shell_meta must succeed");
+        assert_eq!(v, "{a#$b}");
+        assert_eq!(r, "${c/${d}/$(e)} f");
+        assert_eq!(shell_meta(r).expect("shell_meta must succeed").1, "{c/${d}/$(e)}");
+    }
+
+    #[test]
+    fn token_shell_word() {
+        let (rest, w) = shell_word("a'b c'\"d e\"f g").expect("shell_word must succeed");
+        assert_eq!(w, "a'b c'\"d e\"f");
+        assert_eq!(rest, " g");
+
+        let (_, w) = shell_word(r"a\ b").expect("shell_word must succeed");
+        assert_eq!(w, r"a\ b");
+
+        let (_, w) = shell_word("$(x y)z").expect("shell_word must succeed");
+        assert_eq!(w, "$(x y)z");
+
+        let (rest, w) = shell_word("arr=(x [y]=z) w").expect("shell_word must succeed");
+        assert_eq!(w, "arr=(x [y]=z)");
+        assert_eq!(rest, " w");
+
+        assert!(shell_word(" a").is_err());
+        assert!(shell_word("'open").is_err());
     }
 
     #[test]
     fn token_keyval() {
-        let (rest, (key, val)) = keyval("hello=world pair")
+        let (rest, (key, op, val)) = keyval("hello=world pair")
             .expect("keyval must succeed");
         assert_eq!(key, "hello");
+        assert_eq!(op, "=");
         assert_eq!(val, "world");
         assert_eq!(rest, " pair");
+
+        let (rest, (key, op, val)) = keyval("a+=(b 'c )') d")
+            .expect("keyval must succeed");
+        assert_eq!((key, op), ("a", "+="));
+        assert_eq!(val, "(b 'c )')");
+        assert_eq!(rest, " d");
     }
 
     #[test]
@@ -501,7 +1158,7 @@ mod tests {
         assert!(keyval("one two=three").is_err());
         assert!(keyval("one=\"two three\"").is_ok());
         match keyval("one=\"two three\"") {
-            Ok((_, (_, val))) => assert_eq!(val, "two three"),
+            Ok((_, (_, _, val))) => assert_eq!(val, "two three"),
             _ => panic!("keyval must succeed"),
         }
     }